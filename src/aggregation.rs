@@ -0,0 +1,98 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+/// Method used to reduce the color sensor samples of a facelet into a single reading
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Aggregation {
+    /// Average of the samples
+    #[default]
+    Mean,
+    /// Median of the samples, channel by channel
+    Median,
+    /// Average of the samples once the lowest and highest quarter of each channel are dropped
+    TrimmedMean,
+    /// Average of the samples within 3 median absolute deviations of the median, channel by channel
+    Mad,
+}
+
+impl Aggregation {
+    /// Reduces the samples, of which there must be at least one, into a single RGB reading
+    pub fn aggregate(&self, samples: &[[f64; 3]]) -> [f64; 3] {
+        [0, 1, 2].map(|i| {
+            let channel = samples
+                .iter()
+                .map(|x| x[i])
+                .sorted_by(f64::total_cmp)
+                .collect_vec();
+            match self {
+                Aggregation::Mean => mean(&channel),
+                Aggregation::Median => median(&channel),
+                Aggregation::TrimmedMean => {
+                    let trim = channel.len() / 4;
+                    mean(&channel[trim..channel.len() - trim])
+                }
+                Aggregation::Mad => {
+                    let median = median(&channel);
+                    let deviations = channel
+                        .iter()
+                        .map(|x| (x - median).abs())
+                        .sorted_by(f64::total_cmp)
+                        .collect_vec();
+                    // scaled so that it estimates the standard deviation of normally distributed samples
+                    let mad = 1.4826 * self::median(&deviations);
+                    let kept = channel
+                        .into_iter()
+                        .filter(|x| (x - median).abs() <= 3. * mad)
+                        .collect_vec();
+                    if kept.is_empty() {
+                        median
+                    } else {
+                        mean(&kept)
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Median of already sorted values
+fn median(values: &[f64]) -> f64 {
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[mid]
+    } else {
+        (values[mid - 1] + values[mid]) / 2.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregation::Aggregation;
+
+    #[test]
+    fn test_outlier_rejection() {
+        let samples = [
+            [100., 50., 20.],
+            [102., 52., 21.],
+            [98., 48., 19.],
+            [101., 51., 20.],
+            [400., 10., 90.],
+        ];
+        assert!(Aggregation::Mean.aggregate(&samples)[0] > 150.);
+        for aggregation in [
+            Aggregation::Median,
+            Aggregation::TrimmedMean,
+            Aggregation::Mad,
+        ] {
+            let rgb = aggregation.aggregate(&samples);
+            assert!((rgb[0] - 100.).abs() < 2., "{aggregation:?} gave {rgb:?}");
+            assert!((rgb[1] - 50.).abs() < 2., "{aggregation:?} gave {rgb:?}");
+            assert!((rgb[2] - 20.).abs() < 2., "{aggregation:?} gave {rgb:?}");
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use itertools::Itertools;
+
+use crate::classification::{colour_centroids, FeaturePoint};
+use crate::constants::{CORNER_FACELET, EDGE_FACELET, FACES};
+use crate::fixer::{calculate_score, FixError, FixerConfig, SearchStats};
+
+/// Maximum number of times the colour centroids are re-estimated from the previous solution
+const MAX_REFINEMENTS: usize = 10;
+
+/// Costs of putting every piece of a kind (corners or edges) on every position, with every orientation
+struct PieceCosts {
+    /// Cost indexed by position, piece and orientation
+    costs: Vec<Vec<Vec<f64>>>,
+    /// Pieces of every position, sorted by their lowest cost
+    candidates: Vec<Vec<usize>>,
+}
+
+impl PieceCosts {
+    /// Orientation `o` puts the k-th colour of a piece on the facelet `(o + k) % n` of its position,
+    /// which matches the orientation convention of [kewb::CubieCube].
+    fn init<const N: usize>(
+        pieces: &[[usize; N]],
+        rgb_values: &[FeaturePoint],
+        centroids: &HashMap<char, FeaturePoint>,
+    ) -> Self {
+        let costs = pieces
+            .iter()
+            .map(|position| {
+                pieces
+                    .iter()
+                    .map(|piece| {
+                        (0..N)
+                            .map(|ori| {
+                                (0..N)
+                                    .map(|k| {
+                                        rgb_values[position[(ori + k) % N]]
+                                            .distance_to(&centroids[&FACES[piece[k] / 9]])
+                                    })
+                                    .sum()
+                            })
+                            .collect_vec()
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
+        let candidates = costs
+            .iter()
+            .map(|position: &Vec<Vec<f64>>| {
+                (0..pieces.len())
+                    .sorted_by(|a, b| min(&position[*a]).total_cmp(&min(&position[*b])))
+                    .collect_vec()
+            })
+            .collect_vec();
+        PieceCosts { costs, candidates }
+    }
+
+    fn len(&self) -> usize {
+        self.costs.len()
+    }
+
+    fn orientations(&self) -> usize {
+        self.costs[0][0].len()
+    }
+
+    /// Lower bound of the cost of the positions from `start`, using only the pieces not in `used`
+    fn lower_bound(&self, start: usize, used: u16) -> f64 {
+        (start..self.len())
+            .map(|position| {
+                (0..self.len())
+                    .filter(|piece| used & (1 << piece) == 0)
+                    .map(|piece| min(&self.costs[position][piece]))
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .sum()
+    }
+}
+
+fn min(values: &[f64]) -> f64 {
+    values.iter().copied().fold(f64::INFINITY, f64::min)
+}
+
+/// Partial assignment of one kind of pieces
+#[derive(Clone, Copy, Default)]
+struct KindState {
+    /// Pieces already placed
+    used: u16,
+    /// Sum of the orientations of the placed pieces, modulo the number of orientations
+    orientation: usize,
+    /// Parity of the permutation of the placed pieces
+    parity: usize,
+}
+
+/// Depth-first branch and bound over corner then edge assignments
+struct Search<'a> {
+    kinds: [PieceCosts; 2],
+    /// Piece and orientation of every position of each kind
+    current: [Vec<(usize, usize)>; 2],
+    best_cost: f64,
+    best: Option<[Vec<(usize, usize)>; 2]>,
+    /// Number of partial assignments explored
+    nodes: usize,
+    config: &'a FixerConfig,
+    start: Instant,
+    /// Whether the search ran out of time or was cancelled
+    stopped: bool,
+}
+
+impl Search<'_> {
+    fn explore(&mut self, kind: usize, position: usize, states: [KindState; 2], cost: f64) {
+        if self.stopped {
+            return;
+        }
+        self.nodes += 1;
+        if self.config.should_stop(self.start) {
+            self.stopped = true;
+            return;
+        }
+        if kind == 2 {
+            if states[0].parity == states[1].parity && cost < self.best_cost {
+                self.best_cost = cost;
+                self.best = Some(self.current.clone());
+            }
+            return;
+        }
+        let costs = &self.kinds[kind];
+        if position == costs.len() {
+            self.explore(kind + 1, 0, states, cost);
+            return;
+        }
+        let mut bound = cost + costs.lower_bound(position, states[kind].used);
+        if kind == 0 {
+            bound += self.kinds[1].lower_bound(0, 0);
+        }
+        if bound >= self.best_cost {
+            return;
+        }
+        let orientations = costs.orientations();
+        let last = position == costs.len() - 1;
+        for piece in self.kinds[kind].candidates[position].clone() {
+            let state = states[kind];
+            if state.used & (1 << piece) != 0 {
+                continue;
+            }
+            // inversions added by this piece are the greater pieces already placed
+            let inversions = (state.used >> piece).count_ones() as usize;
+            for ori in 0..orientations {
+                let total = (state.orientation + ori) % orientations;
+                if last && total != 0 {
+                    continue;
+                }
+                let mut next = states;
+                next[kind] = KindState {
+                    used: state.used | (1 << piece),
+                    orientation: total,
+                    parity: (state.parity + inversions) % 2,
+                };
+                self.current[kind][position] = (piece, ori);
+                let piece_cost = self.kinds[kind].costs[position][piece][ori];
+                self.explore(kind, position + 1, next, cost + piece_cost);
+            }
+        }
+    }
+}
+
+/// Finds the valid cube state closest to the readings, given fixed colour centroids.
+/// The cost of a state is the sum of the distances between every corner and edge facelet and the centroid of its colour.
+/// Returns the best state found with its cost, if any, and adds the explored partial assignments to the statistics.
+/// If the search runs out of time or is cancelled, the state is the best one found until then.
+fn solve_for_centroids(
+    rgb_values: &[FeaturePoint],
+    centroids: &HashMap<char, FeaturePoint>,
+    stats: &mut SearchStats,
+    config: &FixerConfig,
+    start: Instant,
+) -> Option<(f64, String)> {
+    let corners = PieceCosts::init(&CORNER_FACELET, rgb_values, centroids);
+    let edges = PieceCosts::init(&EDGE_FACELET, rgb_values, centroids);
+    let mut search = Search {
+        current: [vec![(0, 0); corners.len()], vec![(0, 0); edges.len()]],
+        kinds: [corners, edges],
+        best_cost: f64::INFINITY,
+        best: None,
+        nodes: 0,
+        config,
+        start,
+        stopped: false,
+    };
+    search.explore(0, 0, [KindState::default(); 2], 0.);
+    stats.candidates += search.nodes;
+    stats.stopped |= search.stopped;
+    let [corners, edges] = search.best?;
+    let mut notation = vec![' '; 54];
+    for (i, face) in FACES.iter().enumerate() {
+        notation[9 * i + 4] = *face;
+    }
+    for (position, (piece, ori)) in corners.into_iter().enumerate() {
+        for k in 0..3 {
+            notation[CORNER_FACELET[position][(ori + k) % 3]] = FACES[CORNER_FACELET[piece][k] / 9];
+        }
+    }
+    for (position, (piece, ori)) in edges.into_iter().enumerate() {
+        for k in 0..2 {
+            notation[EDGE_FACELET[position][(ori + k) % 2]] = FACES[EDGE_FACELET[piece][k] / 9];
+        }
+    }
+    Some((search.best_cost, notation.iter().collect()))
+}
+
+/// Colour centroids of the notation. Colours missing from it are seeded with the reading of their centre
+fn seeded_centroids(rgb_values: &[FeaturePoint], notation: &str) -> HashMap<char, FeaturePoint> {
+    let mut centroids = colour_centroids(rgb_values, &notation.chars().collect_vec());
+    for (i, face) in FACES.iter().enumerate() {
+        centroids.entry(*face).or_insert(rgb_values[9 * i + 4]);
+    }
+    centroids
+}
+
+/// Finds a valid notation for the given (possibly invalid) notation by alternating assignment and centroid estimation.
+/// This fixer is heuristic: it is not guaranteed to find the valid notation with the lowest [calculate_score].
+/// Unlike [find_optimal_fix](crate::fixer::find_optimal_fix), no local search is involved:
+/// for given colour centroids, corners and edges are assigned by branch and bound, under the same piece, orientation
+/// and parity constraints as `CubieCube::try_from`, so the best state for these centroids is always found.
+/// The centroids start as the colour means of the given notation and are re-estimated from the solution until it stops changing,
+/// and the notation with the lowest score over these iterations is kept.
+/// The search stops early as described in [FixerConfig].
+///
+/// # Arguments
+/// * `rgb_values` - The previously scanned facelet RGB tuples.
+/// * `nota` - The initial notation string to be fixed.
+/// * `config` - The fixer settings, giving the time budget and the cancellation flag.
+///
+/// # Returns
+/// The fixed notation (String) with its [calculate_score] (f64),
+/// or an error if no valid notation was found, along with the statistics of the search.
+pub fn find_assignment_fix(
+    rgb_values: &[FeaturePoint],
+    nota: String,
+    config: &FixerConfig,
+) -> (Result<(f64, String), FixError>, SearchStats) {
+    let start = Instant::now();
+    let mut stats = SearchStats::default();
+    let mut notation = nota;
+    let mut best: Option<(f64, String)> = None;
+    for _ in 0..MAX_REFINEMENTS {
+        let centroids = seeded_centroids(rgb_values, &notation);
+        let Some((_, fixed)) =
+            solve_for_centroids(rgb_values, &centroids, &mut stats, config, start)
+        else {
+            break;
+        };
+        let converged = fixed == notation;
+        let score = calculate_score(rgb_values, &fixed);
+        if !best
+            .as_ref()
+            .is_some_and(|(best_score, _)| *best_score <= score)
+        {
+            best = Some((score, fixed.clone()));
+        }
+        notation = fixed;
+        if converged || stats.stopped {
+            break;
+        }
+    }
+    stats.elapsed = start.elapsed();
+    let error = if stats.stopped {
+        FixError::Stopped
+    } else {
+        FixError::NoValidState
+    };
+    (best.ok_or(error), stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use kewb::{CubieCube, FaceCube};
+
+    use crate::assignment_fixer::find_assignment_fix;
+    use crate::cube::Cube;
+    use crate::fixer::{calculate_score, FixerConfig};
+
+    #[test]
+    fn assignment_fix_is_valid() {
+        for file in [
+            "scan_test_files/solvable.txt",
+            "scan_test_files/hard_to_solve.txt",
+        ] {
+            let mut cube = Cube::init();
+            cube.import(file.to_string())
+                .expect("Could not load scan file");
+            let points = cube.feature_points();
+            let (fixed, stats) =
+                find_assignment_fix(&points, cube.to_notation(), &FixerConfig::default());
+            assert!(!stats.stopped);
+            let (score, fixed) = fixed.unwrap();
+            // the score is the one every fixer reports
+            assert!((score - calculate_score(&points, &fixed)).abs() < 1e-9);
+            let face_cube = FaceCube::try_from(fixed.as_str()).unwrap();
+            assert!(
+                CubieCube::try_from(&face_cube).is_ok(),
+                "{fixed} is invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_colour_is_seeded_from_its_centre() {
+        let mut cube = Cube::init();
+        cube.import("scan_test_files/solvable.txt".to_string())
+            .expect("Could not load scan file");
+        let points = cube.feature_points();
+        let notation = cube.to_notation();
+        // no facelet, not even the centre, is classified as B
+        let missing = notation.replace('B', "U");
+        let (fixed, _) = find_assignment_fix(&points, missing, &FixerConfig::default());
+        let (_, fixed) = fixed.unwrap();
+        assert_eq!(fixed.chars().filter(|x| *x == 'B').count(), 9);
+        let face_cube = FaceCube::try_from(fixed.as_str()).unwrap();
+        assert!(CubieCube::try_from(&face_cube).is_ok());
+    }
+}
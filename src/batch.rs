@@ -0,0 +1,228 @@
+use std::io::Write;
+use std::time::Instant;
+
+use kewb::DataTable;
+use serde::Serialize;
+
+use crate::bench::Pipeline;
+use crate::cube::Cube;
+use crate::diagnosis::diagnose;
+
+/// Format of the rows written by the batch and dataset export modes
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum BatchFormat {
+    /// Comma-separated values, with a header line
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// Outcome of the classification, fixing and solving of a scan file
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct BatchRow {
+    pub file: String,
+    /// Notation given by the classifier
+    pub classified: Option<String>,
+    /// True if the classified notation was already a valid cube
+    pub classified_valid: Option<bool>,
+    /// Valid notation found by the fixer
+    pub fixed: Option<String>,
+    /// Score of the fixed notation
+    pub score: Option<f64>,
+    /// True if a valid notation was found and solved
+    pub valid: bool,
+    pub solution: Option<String>,
+    /// Number of moves of the solution
+    pub solution_length: Option<usize>,
+    /// Time spent classifying (in ms)
+    pub classify_ms: Option<f64>,
+    /// Time spent fixing (in ms)
+    pub fix_ms: Option<f64>,
+    /// Time spent solving (in ms)
+    pub solve_ms: Option<f64>,
+    /// Reason why the file could not be fully processed
+    pub error: Option<String>,
+}
+
+/// Names of the columns of the CSV format, in the order of the fields of [BatchRow]
+const CSV_HEADER: [&str; 12] = [
+    "file",
+    "classified",
+    "classified_valid",
+    "fixed",
+    "score",
+    "valid",
+    "solution",
+    "solution_length",
+    "classify_ms",
+    "fix_ms",
+    "solve_ms",
+    "error",
+];
+
+/// Quotes a CSV field if it holds a separator, a quote or a line break
+pub fn csv_field(field: String) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// Writes an optional CSV value, leaving it empty if it is missing
+pub fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map_or(String::new(), T::to_string)
+}
+
+impl BatchRow {
+    /// Writes the row as a CSV line, leaving missing values empty
+    pub fn to_csv(&self) -> String {
+        [
+            self.file.clone(),
+            optional(&self.classified),
+            optional(&self.classified_valid),
+            optional(&self.fixed),
+            optional(&self.score),
+            self.valid.to_string(),
+            optional(&self.solution),
+            optional(&self.solution_length),
+            optional(&self.classify_ms),
+            optional(&self.fix_ms),
+            optional(&self.solve_ms),
+            optional(&self.error),
+        ]
+        .map(csv_field)
+        .join(",")
+    }
+}
+
+fn milliseconds(start: Instant) -> Option<f64> {
+    Some(start.elapsed().as_secs_f64() * 1000.)
+}
+
+/// Classifies, fixes and solves a scan file. Failures are recorded as described in [Pipeline]
+pub fn process(pipeline: &Pipeline, table: &DataTable, file: &str) -> BatchRow {
+    let mut row = BatchRow {
+        file: file.to_string(),
+        ..Default::default()
+    };
+    let cube = match pipeline.load(file) {
+        Ok(cube) => cube,
+        Err(e) => {
+            row.error = Some(e);
+            return row;
+        }
+    };
+    let start = Instant::now();
+    let classified = cube.to_notation_with(pipeline.classifier);
+    row.classify_ms = milliseconds(start);
+    row.classified_valid = Some(diagnose(&classified).is_empty());
+    row.classified = Some(classified.clone());
+
+    let start = Instant::now();
+    let fixed = pipeline.fix(&cube, classified);
+    row.fix_ms = milliseconds(start);
+    let report = match fixed {
+        Ok(report) => report,
+        Err(e) => {
+            row.error = Some(e.to_string());
+            return row;
+        }
+    };
+    row.score = Some(report.score);
+    row.fixed = Some(report.fixed.clone());
+
+    let start = Instant::now();
+    let solution = Cube::try_solve(table, &report.fixed);
+    row.solve_ms = milliseconds(start);
+    match solution {
+        Some(solution) => {
+            row.valid = true;
+            row.solution_length = Some(solution.len());
+            row.solution = Some(solution.to_string());
+        }
+        None => row.error = Some("Could not solve the fixed notation".to_string()),
+    }
+    row
+}
+
+/// Processes the scan files one after the other, writing a row for every file as soon as it is processed
+pub fn batch(
+    pipeline: &Pipeline,
+    table: &DataTable,
+    files: &[String],
+    format: BatchFormat,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    if format == BatchFormat::Csv {
+        writeln!(output, "{}", CSV_HEADER.join(","))?;
+    }
+    for file in files {
+        let row = process(pipeline, table, file);
+        match format {
+            BatchFormat::Csv => writeln!(output, "{}", row.to_csv())?,
+            BatchFormat::Jsonl => writeln!(output, "{}", serde_json::to_string(&row)?)?,
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kewb::DataTable;
+
+    use crate::batch::{csv_field, process, BatchRow, CSV_HEADER};
+    use crate::bench::Pipeline;
+    use crate::classification::Classifier;
+    use crate::fixer::Fixer;
+
+    #[test]
+    fn test_batch_row_formats() {
+        let row = BatchRow {
+            file: "scans/a,b".to_string(),
+            classified: Some("UUU".to_string()),
+            classified_valid: Some(false),
+            error: Some("No valid \"state\"".to_string()),
+            ..Default::default()
+        };
+        let csv = row.to_csv();
+        assert_eq!(
+            csv,
+            "\"scans/a,b\",UUU,false,,,false,,,,,,\"No valid \"\"state\"\"\""
+        );
+        let json = serde_json::to_value(&row).unwrap();
+        let keys = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut header = CSV_HEADER.map(String::from).to_vec();
+        header.sort();
+        assert_eq!(keys, header);
+        assert_eq!(csv_field("R U2".to_string()), "R U2");
+    }
+
+    #[test]
+    fn test_process() {
+        let pipeline = Pipeline {
+            classifier: Classifier::Cubie,
+            fixer: Fixer::Assignment,
+            ..Default::default()
+        };
+        let table = DataTable::default();
+        let row = process(&pipeline, &table, "scan_test_files/solvable.txt");
+        assert_eq!(row.error, None);
+        assert!(row.valid);
+        assert_eq!(row.fixed.as_deref(), row.classified.as_deref());
+        assert!(row.score.is_some());
+        let solution = row.solution.unwrap();
+        assert_eq!(
+            row.solution_length,
+            Some(solution.split_whitespace().count())
+        );
+        assert!(row.classify_ms.is_some() && row.fix_ms.is_some() && row.solve_ms.is_some());
+    }
+}
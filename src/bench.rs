@@ -0,0 +1,335 @@
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use paris::{info, log, success, warn};
+
+use crate::aggregation::Aggregation;
+use crate::calibration::Calibration;
+use crate::classification::{Classifier, FeatureWeights};
+use crate::constants::FACES;
+use crate::cube::Cube;
+use crate::fixer::{fix_with, FixError, Fixer, FixerConfig};
+use crate::report::FixReport;
+
+/// Settings of the classification and fixing steps applied to a scan.
+/// The modes running a pipeline over many scan files, [bench], [batch](crate::batch::batch)
+/// and [export_dataset](crate::dataset::export_dataset), record the files that cannot be loaded or fixed
+/// along with the error instead of stopping
+#[derive(Clone)]
+pub struct Pipeline {
+    pub classifier: Classifier,
+    pub fixer: Fixer,
+    /// Aggregation recomputing the readings from the raw samples, if any
+    pub aggregation: Option<Aggregation>,
+    pub luminance_weight: f64,
+    pub unreliable_centres: Vec<char>,
+    pub calibration: Option<Calibration>,
+    pub config: FixerConfig,
+}
+
+impl Default for Pipeline {
+    /// Default settings of the command line
+    fn default() -> Self {
+        Pipeline {
+            classifier: Classifier::default(),
+            fixer: Fixer::default(),
+            aggregation: None,
+            luminance_weight: FeatureWeights::default().luminance,
+            unreliable_centres: vec![],
+            calibration: None,
+            config: FixerConfig::default(),
+        }
+    }
+}
+
+impl Pipeline {
+    /// Short description of the settings, used to tell pipelines apart
+    pub fn name(&self) -> String {
+        let mut name = format!("{:?}/{:?}", self.classifier, self.fixer).to_lowercase();
+        if let Some(aggregation) = self.aggregation {
+            name.push_str(&format!("/{aggregation:?}").to_lowercase());
+        }
+        if self.fixer == Fixer::Search {
+            name.push_str(&format!("/{:?}", self.config.objective).to_lowercase());
+        }
+        name.push_str(&format!("/luminance={}", self.luminance_weight));
+        if self.calibration.is_some() {
+            name.push_str("/calibrated");
+        }
+        name
+    }
+
+    /// Loads the scan file, recomputing its readings with the aggregation, if any, without correcting them.
+    /// Returns the reason why the file could not be loaded otherwise
+    pub fn read(&self, file: &str) -> Result<Cube, String> {
+        let mut cube = Cube::init();
+        cube.feature_weights.luminance = self.luminance_weight;
+        cube.unreliable_centres = self.unreliable_centres.clone();
+        cube.import(file.to_string())
+            .and_then(|_| match self.aggregation {
+                Some(aggregation) => cube.reaggregate(aggregation),
+                None => Ok(()),
+            })
+            .map_err(|e| format!("Could not load scan file: {e}"))?;
+        Ok(cube)
+    }
+
+    /// Corrects the readings of a read scan with the calibration, if any
+    pub fn correct(&self, cube: &mut Cube) -> Result<(), String> {
+        match &self.calibration {
+            Some(calibration) => calibration
+                .correct(cube)
+                .map_err(|e| format!("Could not apply calibration profile: {e}")),
+            None => Ok(()),
+        }
+    }
+
+    /// Loads the scan file, recomputing its readings with the aggregation and correcting them with the calibration, if any
+    pub fn load(&self, file: &str) -> Result<Cube, String> {
+        let mut cube = self.read(file)?;
+        self.correct(&mut cube)?;
+        Ok(cube)
+    }
+
+    /// Turns the classified notation of a loaded scan into a valid one
+    pub fn fix(&self, cube: &Cube, classified: String) -> Result<FixReport, FixError> {
+        fix_with(self.fixer, &cube.feature_points(), classified, &self.config)
+    }
+
+    /// Loads the scan file and returns its classified notation, its fixed notation if any was found,
+    /// and the true notation recorded in the file, if any
+    pub fn run(&self, file: &str) -> Result<(String, Option<String>, Option<String>), String> {
+        let cube = self.load(file)?;
+        let classified = cube.to_notation_with(self.classifier);
+        let fixed = self
+            .fix(&cube, classified.clone())
+            .ok()
+            .map(|report| report.fixed);
+        Ok((classified, fixed, cube.truth))
+    }
+}
+
+/// Outcome of a pipeline on a scan whose true notation is known
+pub struct FileResult {
+    pub file: String,
+    /// Number of facelets classified with the wrong colour, before fixing
+    pub classified_errors: usize,
+    /// Number of facelets with the wrong colour after fixing, or None if the fixer found no valid state
+    pub fixed_errors: Option<usize>,
+    /// Time spent classifying and fixing
+    pub elapsed: Duration,
+    /// Number of facelets of every true colour (rows) given every colour (columns) after fixing, in the order of [FACES]
+    pub confusion: [[usize; 6]; 6],
+    /// Reason why the pipeline could not run on the file, in which case nothing is counted
+    pub error: Option<String>,
+}
+
+impl FileResult {
+    /// Returns true if the fixed notation is the true one
+    pub fn correct(&self) -> bool {
+        self.fixed_errors == Some(0)
+    }
+}
+
+fn errors(truth: &[char], notation: &str) -> usize {
+    truth
+        .iter()
+        .zip(notation.chars())
+        .filter(|(a, b)| **a != *b)
+        .count()
+}
+
+/// Runs the pipeline over the scan files, comparing the results to their true notation.
+/// `truth` is used for the files that do not record their true notation.
+/// Failures are recorded as described in [Pipeline].
+pub fn bench(pipeline: &Pipeline, files: &[String], truth: &str) -> Vec<FileResult> {
+    files
+        .iter()
+        .map(|file| {
+            let start = Instant::now();
+            let (classified, fixed, recorded) = match pipeline.run(file) {
+                Ok(result) => result,
+                Err(e) => {
+                    return FileResult {
+                        file: file.clone(),
+                        classified_errors: 0,
+                        fixed_errors: None,
+                        elapsed: start.elapsed(),
+                        confusion: [[0; 6]; 6],
+                        error: Some(e),
+                    }
+                }
+            };
+            let elapsed = start.elapsed();
+            let truth = recorded.as_deref().unwrap_or(truth).chars().collect_vec();
+            let mut confusion = [[0; 6]; 6];
+            let predicted = fixed.as_ref().unwrap_or(&classified);
+            for (t, p) in truth.iter().zip(predicted.chars()) {
+                if let (Some(t), Some(p)) = (
+                    FACES.iter().position(|x| x == t),
+                    FACES.iter().position(|x| *x == p),
+                ) {
+                    confusion[t][p] += 1;
+                }
+            }
+            FileResult {
+                file: file.clone(),
+                classified_errors: errors(&truth, &classified),
+                fixed_errors: fixed.map(|x| errors(&truth, &x)),
+                elapsed,
+                confusion,
+                error: None,
+            }
+        })
+        .collect()
+}
+
+/// Nearest-rank percentile of sorted durations
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100. * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Prints the results of several pipelines over the same files side by side, followed by their summaries
+pub fn print_comparison(results: &[(String, Vec<FileResult>)]) {
+    let width = results
+        .iter()
+        .flat_map(|(_, x)| x.iter().map(|r| r.file.len()))
+        .max()
+        .unwrap_or(0);
+    let column = results
+        .iter()
+        .map(|(name, _)| name.len())
+        .fold(29, usize::max);
+    let header = results
+        .iter()
+        .map(|(name, _)| format!("{name:>column$}"))
+        .join(" ");
+    info!("{:width$} {header}", "file");
+    for i in 0..results.first().map_or(0, |x| x.1.len()) {
+        let columns = results
+            .iter()
+            .map(|(_, x)| {
+                let r = &x[i];
+                if r.error.is_some() {
+                    return format!("{:>column$}", "ERROR");
+                }
+                let fixed = r
+                    .fixed_errors
+                    .map_or("invalid".to_string(), |x| x.to_string());
+                let status = if r.correct() { "ok" } else { "FAIL" };
+                let cell = format!(
+                    "{status:>4} {:>3} -> {fixed:>7} {:>9.2?}",
+                    r.classified_errors, r.elapsed
+                );
+                format!("{cell:>column$}")
+            })
+            .join(" ");
+        log!("{:width$} {columns}", results[0].1[i].file);
+    }
+    for (name, results) in results {
+        print_summary(name, results);
+    }
+}
+
+/// Prints the accuracy, error counts, confusion matrix and timing percentiles of a pipeline, and the files it failed on
+fn print_summary(name: &str, results: &[FileResult]) {
+    let correct = results.iter().filter(|x| x.correct()).count();
+    success!("{name}: {correct} / {} scans correct", results.len());
+    for result in results {
+        if let Some(error) = &result.error {
+            warn!("{}: {error}", result.file);
+        }
+    }
+    let results = results.iter().filter(|x| x.error.is_none()).collect_vec();
+    log!(
+        "Wrong facelets: {} classified, {} fixed, {} scans without a valid state",
+        results.iter().map(|x| x.classified_errors).sum::<usize>(),
+        results.iter().filter_map(|x| x.fixed_errors).sum::<usize>(),
+        results.iter().filter(|x| x.fixed_errors.is_none()).count()
+    );
+    log!("Confusion matrix (rows are true colours):");
+    log!("    {}", FACES.iter().map(|x| format!("{x:>5}")).join(""));
+    for (t, face) in FACES.iter().enumerate() {
+        let row = (0..6)
+            .map(|p| results.iter().map(|x| x.confusion[t][p]).sum::<usize>())
+            .map(|x| format!("{x:>5}"))
+            .join("");
+        log!("  {face} {row}");
+    }
+    let times = results.iter().map(|x| x.elapsed).sorted().collect_vec();
+    log!(
+        "Time: p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, max {:.2?}",
+        percentile(&times, 50.),
+        percentile(&times, 90.),
+        percentile(&times, 99.),
+        percentile(&times, 100.)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_dir;
+    use std::time::Duration;
+
+    use kewb::DataTable;
+
+    use crate::batch::process;
+    use crate::bench::{bench, percentile, Pipeline};
+    use crate::classification::Classifier;
+    use crate::dataset::rows;
+    use crate::fixer::Fixer;
+
+    #[test]
+    fn test_bench_solved_scans() {
+        let files = read_dir("scan_test_files/official_cube_solved")
+            .unwrap()
+            .flatten()
+            .map(|x| x.path().to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        let pipeline = Pipeline {
+            classifier: Classifier::Cubie,
+            fixer: Fixer::Assignment,
+            ..Default::default()
+        };
+        let truth = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        let results = bench(&pipeline, &files, truth);
+        assert_eq!(results.len(), files.len());
+        for result in results {
+            assert!(result.correct(), "{} was not fixed", result.file);
+            for (i, row) in result.confusion.iter().enumerate() {
+                assert_eq!(row[i], 9);
+            }
+        }
+        assert_eq!(pipeline.name(), "cubie/assignment/luminance=0.25");
+
+        let times = [1, 2, 3, 4].map(Duration::from_millis);
+        assert_eq!(percentile(&times, 50.), Duration::from_millis(2));
+        assert_eq!(percentile(&times, 100.), Duration::from_millis(4));
+    }
+
+    #[test]
+    fn failed_files_are_recorded_by_every_mode() {
+        let pipeline = Pipeline::default();
+        let file = "scan_test_files/missing";
+        let error = pipeline.load(file).err().unwrap();
+        assert!(error.starts_with("Could not load scan file"));
+
+        let results = bench(&pipeline, &[file.to_string()], "");
+        assert_eq!(results[0].error.as_ref(), Some(&error));
+        assert!(!results[0].correct());
+
+        let row = process(&pipeline, &DataTable::default(), file);
+        assert_eq!(row.error.as_ref(), Some(&error));
+        assert!(!row.valid);
+        assert_eq!(row.classified, None);
+
+        let rows = rows(&pipeline, file, None);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].error.as_ref(), Some(&error));
+        assert_eq!(rows[0].facelet, None);
+    }
+}
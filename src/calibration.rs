@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{Read, Write};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::classification::{solve_assignment, ColorPoint, FeaturePoint};
+use crate::constants::{CENTRE_INDICES, FACES, SIDE_INDICES};
+use crate::cube::Cube;
+use crate::scan_file::ReadingUnit;
+
+/// Kind of position a facelet is read at. Each kind is scanned with a different sensor arm position.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionClass {
+    Centre,
+    Edge,
+    Corner,
+}
+
+impl PositionClass {
+    /// Returns the position class of the facelet at `index` in the standard notation
+    pub fn of(index: usize) -> Self {
+        if CENTRE_INDICES.contains(&index) {
+            PositionClass::Centre
+        } else if SIDE_INDICES.contains(&index) {
+            PositionClass::Edge
+        } else {
+            PositionClass::Corner
+        }
+    }
+}
+
+impl Display for PositionClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PositionClass::Centre => write!(f, "centre"),
+            PositionClass::Edge => write!(f, "edge"),
+            PositionClass::Corner => write!(f, "corner"),
+        }
+    }
+}
+
+/// Smallest variance of a feature, so that constant features do not make the likelihood infinite
+const MIN_VARIANCE: f64 = 1e-6;
+
+/// Normal distribution of the features of the readings of a colour, with independent features
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Gaussian {
+    pub mean: [f64; 4],
+    pub variance: [f64; 4],
+}
+
+impl Gaussian {
+    /// Negative log-likelihood of the features of the point, up to a constant
+    pub fn negative_log_likelihood(&self, point: &FeaturePoint) -> f64 {
+        point
+            .to_features()
+            .iter()
+            .zip(self.mean.iter().zip(&self.variance))
+            .map(|(x, (mean, variance))| 0.5 * ((x - mean).powi(2) / variance + variance.ln()))
+            .sum()
+    }
+}
+
+fn mean(features: &[[f64; 4]]) -> [f64; 4] {
+    let n = features.len() as f64;
+    [0, 1, 2, 3].map(|i| features.iter().map(|x| x[i]).sum::<f64>() / n)
+}
+
+/// Distribution of the features of every colour, estimated from labelled scans.
+/// Colours are only known through the faces they were on, so they are matched to the faces of a scan by their centres.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ColourModel {
+    pub colours: Vec<Gaussian>,
+}
+
+impl ColourModel {
+    /// Estimates the distribution of every colour from feature points whose true notation is known.
+    /// The same face can hold a different colour in every scan, so the faces of every scan are first matched
+    /// to the colours of the first scan by the distance between their mean features.
+    pub fn estimate(scans: &[(Vec<FeaturePoint>, String)]) -> Self {
+        let mut features: Vec<Vec<[f64; 4]>> = vec![vec![]; FACES.len()];
+        let mut reference: Option<Vec<[f64; 4]>> = None;
+        for (points, truth) in scans {
+            let truth = truth.chars().collect_vec();
+            let faces = FACES
+                .iter()
+                .map(|face| {
+                    points
+                        .iter()
+                        .filter(|x| truth[x.index] == *face)
+                        .map(|x| x.to_features())
+                        .collect_vec()
+                })
+                .collect_vec();
+            if faces.iter().any(|x| x.is_empty()) {
+                continue;
+            }
+            let means = faces.iter().map(|x| mean(x)).collect_vec();
+            let reference = reference.get_or_insert_with(|| means.clone());
+            let costs = means
+                .iter()
+                .map(|a| {
+                    reference
+                        .iter()
+                        .map(|b| a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum())
+                        .collect_vec()
+                })
+                .collect_vec();
+            for (face, colour) in solve_assignment(&costs).into_iter().enumerate() {
+                features[colour].extend(&faces[face]);
+            }
+        }
+        let colours = features
+            .into_iter()
+            .filter(|x| !x.is_empty())
+            .map(|features| {
+                let n = features.len() as f64;
+                let mean = mean(&features);
+                let variance = [0, 1, 2, 3].map(|i| {
+                    let variance = features
+                        .iter()
+                        .map(|x| (x[i] - mean[i]).powi(2))
+                        .sum::<f64>()
+                        / n;
+                    variance.max(MIN_VARIANCE)
+                });
+                Gaussian { mean, variance }
+            })
+            .collect();
+        ColourModel { colours }
+    }
+
+    /// Returns the distribution of the colour of every face, in the order of [FACES],
+    /// by matching the colours to the centre readings of the scan
+    pub fn faces(&self, points: &[FeaturePoint]) -> Vec<Gaussian> {
+        let costs = (0..FACES.len())
+            .map(|i| {
+                self.colours
+                    .iter()
+                    .map(|colour| colour.negative_log_likelihood(&points[9 * i + 4]))
+                    .collect_vec()
+            })
+            .collect_vec();
+        solve_assignment(&costs)
+            .into_iter()
+            .map(|colour| self.colours[colour].clone())
+            .collect()
+    }
+}
+
+/// Sensor calibration profile, estimated from labelled scans.
+/// Offsets are expressed in the same unit as the scans they were estimated from.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Calibration {
+    /// Unit of the scans the profile was estimated from. Missing from older profiles, which are applied to any scan
+    #[serde(default)]
+    pub unit: Option<ReadingUnit>,
+    /// RGB bias of every facelet position, in the order of the standard notation
+    pub offsets: Vec<[f64; 3]>,
+    /// Distribution of the features of every colour, once the bias is removed. Missing from older profiles
+    #[serde(default)]
+    pub model: Option<ColourModel>,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            unit: None,
+            offsets: vec![[0.; 3]; 54],
+            model: None,
+        }
+    }
+}
+
+impl Calibration {
+    /// Estimates the sensor bias from scans whose true notation is known.
+    /// The bias of a reading is its difference to the mean of the readings of the same colour in the same scan.
+    /// Biases are then averaged per position class, or per facelet if `per_index` is set.
+    pub fn estimate(scans: &[(Vec<ColorPoint>, String)], per_index: bool) -> Self {
+        let mut sums: HashMap<usize, ([f64; 3], usize)> = HashMap::new();
+        for (points, truth) in scans {
+            let truth = truth.chars().collect_vec();
+            let mut means: HashMap<char, ([f64; 3], usize)> = HashMap::new();
+            for point in points {
+                let entry = means.entry(truth[point.index]).or_insert(([0.; 3], 0));
+                for (sum, x) in entry.0.iter_mut().zip(point.to_array()) {
+                    *sum += x;
+                }
+                entry.1 += 1;
+            }
+            for point in points {
+                let (sum, count) = means[&truth[point.index]];
+                let key = if per_index {
+                    point.index
+                } else {
+                    PositionClass::of(point.index) as usize
+                };
+                let entry = sums.entry(key).or_insert(([0.; 3], 0));
+                for ((acc, x), mean) in entry.0.iter_mut().zip(point.to_array()).zip(sum) {
+                    *acc += x - mean / count as f64;
+                }
+                entry.1 += 1;
+            }
+        }
+        let offsets = (0..54)
+            .map(|index| {
+                let key = if per_index {
+                    index
+                } else {
+                    PositionClass::of(index) as usize
+                };
+                sums.get(&key)
+                    .map(|(sum, count)| sum.map(|x| x / *count as f64))
+                    .unwrap_or([0.; 3])
+            })
+            .collect();
+        Calibration {
+            unit: None,
+            offsets,
+            model: None,
+        }
+    }
+
+    /// Removes the estimated bias from the given readings
+    pub fn apply(&self, points: &mut [ColorPoint]) {
+        for point in points {
+            let offset = self.offsets[point.index];
+            point.r -= offset[0];
+            point.g -= offset[1];
+            point.b -= offset[2];
+        }
+    }
+
+    /// Removes the estimated bias from the readings of the cube, if they are in the unit of the profile
+    pub fn correct(&self, cube: &mut Cube) -> std::io::Result<()> {
+        match self.unit {
+            Some(unit) if unit != cube.unit => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Calibration profile holds {unit} readings but the scan holds {} readings",
+                    cube.unit
+                ),
+            )),
+            _ => {
+                self.apply(&mut cube.facelet_rgb_values);
+                Ok(())
+            }
+        }
+    }
+
+    /// Loads a calibration profile from a JSON file
+    pub fn load(file_path: &str) -> std::io::Result<Self> {
+        let mut file = File::open(file_path)?;
+        let mut output = String::new();
+        file.read_to_string(&mut output)?;
+        Ok(serde_json::from_str(&output)?)
+    }
+
+    /// Saves the calibration profile to a JSON file
+    pub fn save(&self, file_path: &str) -> std::io::Result<()> {
+        let mut file = File::create(file_path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calibration::{Calibration, PositionClass};
+    use crate::classification::ColorPoint;
+    use crate::cube::Cube;
+    use crate::scan_file::ReadingUnit;
+
+    #[test]
+    fn test_estimate_class_bias() {
+        let truth: String = "URFDLB".chars().flat_map(|c| [c; 9]).collect();
+        let bias = |index: usize| match PositionClass::of(index) {
+            PositionClass::Centre => 0.,
+            PositionClass::Edge => 6.,
+            PositionClass::Corner => -6.,
+        };
+        let mut points = (0..54)
+            .map(|index| ColorPoint {
+                r: (index / 9) as f64 * 100. + bias(index),
+                g: 50.,
+                b: 50. + bias(index),
+                index,
+            })
+            .collect::<Vec<_>>();
+        let calibration = Calibration::estimate(&[(points.clone(), truth)], false);
+        calibration.apply(&mut points);
+        for face in points.chunks(9) {
+            for point in face {
+                assert!((point.r - face[0].r).abs() < 1e-9);
+                assert!((point.b - face[0].b).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_correct_refuses_other_unit() {
+        let mut cube = Cube::init();
+        cube.import("scan_test_files/solvable.txt".to_string())
+            .expect("Could not load scan file");
+        let mut calibration = Calibration {
+            unit: Some(ReadingUnit::Raw),
+            ..Default::default()
+        };
+        assert!(calibration.correct(&mut cube).is_err());
+        calibration.unit = Some(ReadingUnit::Normalised);
+        assert!(calibration.correct(&mut cube).is_ok());
+        calibration.unit = None;
+        assert!(calibration.correct(&mut cube).is_ok());
+    }
+}
@@ -1,6 +1,41 @@
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::constants::{CENTRE_INDICES, CORNER_FACELET, EDGE_FACELET, FACES, SIDE_INDICES};
+
+/// Classification technique used to turn the scanned colours into a notation
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Classifier {
+    /// Classifies facelets one by one, only balancing the number of facelets per colour
+    #[default]
+    Greedy,
+    /// Assigns a valid colour combination and orientation to every piece
+    Cubie,
+    /// Clusters the facelets without using the centres, which only name the clusters afterwards
+    Cluster,
+}
+
+/// Weights of the chromaticity and luminance components of the features used for classification
+#[derive(Copy, Clone, Debug)]
+pub struct FeatureWeights {
+    pub chromaticity: f64,
+    pub luminance: f64,
+}
+
+impl Default for FeatureWeights {
+    fn default() -> Self {
+        FeatureWeights {
+            chromaticity: 1.,
+            luminance: 0.25,
+        }
+    }
+}
+
+/// Reading of the color sensor for a facelet
 #[derive(Copy, Clone, Debug)]
 pub struct ColorPoint {
     pub r: f64,
@@ -9,29 +44,78 @@ pub struct ColorPoint {
     pub index: usize,
 }
 
-impl Hash for ColorPoint {
+impl ColorPoint {
+    /// Returns an array of the three coordinates
+    pub fn to_array(self) -> [f64; 3] {
+        [self.r, self.g, self.b]
+    }
+}
+
+/// Point classification works on, built from a reading by [FeaturePoint::from_reading]
+#[derive(Copy, Clone, Debug)]
+pub struct FeaturePoint {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    /// Luminance component
+    pub l: f64,
+    pub index: usize,
+}
+
+impl Hash for FeaturePoint {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.index.hash(state);
     }
 }
 
-impl Eq for ColorPoint {}
+impl Eq for FeaturePoint {}
 
-impl PartialEq for ColorPoint {
+impl PartialEq for FeaturePoint {
     fn eq(&self, other: &Self) -> bool {
         self.index == other.index
     }
 }
 
-impl ColorPoint {
+impl FeaturePoint {
     pub fn distance_to(&self, other: &Self) -> f64 {
-        ((self.r - other.r).powi(2) + (self.g - other.g).powi(2) + (self.b - other.b).powi(2))
-            .sqrt()
+        ((self.r - other.r).powi(2)
+            + (self.g - other.g).powi(2)
+            + (self.b - other.b).powi(2)
+            + (self.l - other.l).powi(2))
+        .sqrt()
     }
 
-    /// Returns an array of the three coordinates
-    pub fn to_array(&self) -> [f64; 3] {
-        [self.r, self.g, self.b]
+    /// Returns an array of the three chromaticity coordinates and the luminance
+    pub fn to_features(self) -> [f64; 4] {
+        [self.r, self.g, self.b, self.l]
+    }
+
+    /// Builds the feature point of a reading.
+    /// Chromaticity is the reading scaled to unit length, and luminance its length relative to `reference`.
+    /// Without a reference, as for normalised readings whose length carries no information, luminance is 0.
+    pub fn from_reading(
+        reading: &ColorPoint,
+        reference: Option<f64>,
+        weights: FeatureWeights,
+    ) -> Self {
+        let norm = reading
+            .to_array()
+            .iter()
+            .map(|x| x.powi(2))
+            .sum::<f64>()
+            .sqrt();
+        let chromaticity = if norm > 0. {
+            weights.chromaticity / norm
+        } else {
+            0.
+        };
+        FeaturePoint {
+            r: reading.r * chromaticity,
+            g: reading.g * chromaticity,
+            b: reading.b * chromaticity,
+            l: reference.map_or(0., |x| norm / x * weights.luminance),
+            index: reading.index,
+        }
     }
 }
 
@@ -44,15 +128,15 @@ impl ColorPoint {
 /// We are done once all the black points are assigned. We are then sure every point got assigned to a red point that has 8 elements or fewer.
 pub struct Classification {
     /// Centroids
-    red_points: Vec<ColorPoint>,
+    red_points: Vec<FeaturePoint>,
     /// To get classified
-    black_points: Vec<ColorPoint>,
+    black_points: Vec<FeaturePoint>,
     /// Number of elements per red points
     k: i32,
 }
 
 impl Classification {
-    pub fn init(red_points: Vec<ColorPoint>, black_points: Vec<ColorPoint>) -> Self {
+    pub fn init(red_points: Vec<FeaturePoint>, black_points: Vec<FeaturePoint>) -> Self {
         Classification {
             k: (black_points.len() / red_points.len()) as i32,
             red_points,
@@ -60,22 +144,22 @@ impl Classification {
         }
     }
 
-    fn calc_distances(&mut self) -> Vec<(f64, ColorPoint, ColorPoint)> {
-        let mut res: Vec<(f64, ColorPoint, ColorPoint)> = vec![];
+    fn calc_distances(&mut self) -> Vec<(f64, FeaturePoint, FeaturePoint)> {
+        let mut res: Vec<(f64, FeaturePoint, FeaturePoint)> = vec![];
         for bp in &self.black_points {
             for rp in &self.red_points {
-                res.push((bp.distance_to(&rp), bp.clone(), rp.clone()))
+                res.push((bp.distance_to(rp), *bp, *rp))
             }
         }
         res
     }
 
     /// Will return a hashmap with red points as keys and vectors of assigned black points.
-    pub fn classify(&mut self) -> HashMap<ColorPoint, Vec<(f64, ColorPoint)>> {
+    pub fn classify(&mut self) -> HashMap<FeaturePoint, Vec<(f64, FeaturePoint)>> {
         let mut distances = self.calc_distances();
         distances.sort_by(|a, b| a.0.total_cmp(&b.0));
         let mut added = vec![];
-        let mut res: HashMap<ColorPoint, Vec<(f64, ColorPoint)>> = HashMap::new();
+        let mut res: HashMap<FeaturePoint, Vec<(f64, FeaturePoint)>> = HashMap::new();
         for rp in self.red_points.clone() {
             res.insert(rp, Vec::new());
         }
@@ -93,22 +177,396 @@ impl Classification {
     }
 }
 
+/// Computes the mean of the readings of every colour of the notation
+pub fn colour_centroids(
+    rgb_values: &[FeaturePoint],
+    notation: &[char],
+) -> HashMap<char, FeaturePoint> {
+    let mut sums: HashMap<char, ([f64; 4], usize)> = HashMap::new();
+    for point in rgb_values {
+        let entry = sums.entry(notation[point.index]).or_insert(([0.; 4], 0));
+        for (acc, x) in entry.0.iter_mut().zip(point.to_features()) {
+            *acc += x;
+        }
+        entry.1 += 1;
+    }
+    sums.into_iter()
+        .map(|(colour, (sum, count))| {
+            let mean = sum.map(|x| x / count as f64);
+            let centroid = FeaturePoint {
+                r: mean[0],
+                g: mean[1],
+                b: mean[2],
+                l: mean[3],
+                index: 0,
+            };
+            (colour, centroid)
+        })
+        .collect()
+}
+
+/// Returns how confidently every facelet is classified in the notation: the difference between its distance
+/// to the closest centroid of another colour and its distance to the centroid of its own colour.
+/// Facelets closer to another colour than to their own get a negative confidence.
+pub fn confidences(facelets: &[FeaturePoint], notation: &str) -> Vec<f64> {
+    let chars = notation.chars().collect_vec();
+    let centroids = colour_centroids(facelets, &chars);
+    facelets
+        .iter()
+        .map(|point| {
+            let own = point.distance_to(&centroids[&chars[point.index]]);
+            let other = centroids
+                .iter()
+                .filter(|(colour, _)| **colour != chars[point.index])
+                .map(|(_, centroid)| point.distance_to(centroid))
+                .fold(f64::INFINITY, f64::min);
+            other - own
+        })
+        .collect()
+}
+
+/// Returns the indices of the `n` facelets classified with the lowest confidence, least confident first
+pub fn least_confident(facelets: &[FeaturePoint], notation: &str, n: usize) -> Vec<usize> {
+    let confidences = confidences(facelets, notation);
+    facelets
+        .iter()
+        .map(|x| x.index)
+        .sorted_by(|a, b| confidences[*a].total_cmp(&confidences[*b]))
+        .take(n)
+        .collect()
+}
+
+/// Solves the square assignment problem using the Hungarian algorithm.
+/// Returns, for every row of the cost matrix, the column assigned to it so that the total cost is minimal.
+pub fn solve_assignment(costs: &[Vec<f64>]) -> Vec<usize> {
+    let n = costs.len();
+    // potentials and matches are 1-indexed, the column 0 being a virtual one
+    let mut row_potential = vec![0.; n + 1];
+    let mut col_potential = vec![0.; n + 1];
+    let mut col_match = vec![0; n + 1];
+    let mut way = vec![0; n + 1];
+    for row in 1..=n {
+        col_match[0] = row;
+        let mut col = 0;
+        let mut min_slack = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[col] = true;
+            let matched = col_match[col];
+            let mut delta = f64::INFINITY;
+            let mut next_col = 0;
+            for j in 1..=n {
+                if used[j] {
+                    continue;
+                }
+                let slack = costs[matched - 1][j - 1] - row_potential[matched] - col_potential[j];
+                if slack < min_slack[j] {
+                    min_slack[j] = slack;
+                    way[j] = col;
+                }
+                if min_slack[j] < delta {
+                    delta = min_slack[j];
+                    next_col = j;
+                }
+            }
+            for j in 0..=n {
+                if used[j] {
+                    row_potential[col_match[j]] += delta;
+                    col_potential[j] -= delta;
+                } else {
+                    min_slack[j] -= delta;
+                }
+            }
+            col = next_col;
+            if col_match[col] == 0 {
+                break;
+            }
+        }
+        // augmenting path
+        while col != 0 {
+            let prev = way[col];
+            col_match[col] = col_match[prev];
+            col = prev;
+        }
+    }
+    let mut res = vec![0; n];
+    for col in 1..=n {
+        res[col_match[col] - 1] = col - 1;
+    }
+    res
+}
+
+/// Classification working on whole pieces instead of single facelets.
+/// Each corner (or edge) position is given one of the existing corner (or edge) pieces, along with its orientation.
+/// The cost of putting a piece on a position is the sum of the distances between the scanned facelets and the centroid of the colour they would get.
+/// Positions and pieces are then matched by solving the assignment problem, so every piece appears exactly once.
+/// The resulting notation can still have twisted corners, flipped edges or a wrong parity, which is left to the fixer.
+pub struct CubieClassification {
+    /// Colour centroids, in the order of [FACES]
+    centroids: Vec<FeaturePoint>,
+    /// Scanned facelets, in the order of the standard notation
+    facelets: Vec<FeaturePoint>,
+}
+
+impl CubieClassification {
+    pub fn init(centroids: Vec<FeaturePoint>, facelets: Vec<FeaturePoint>) -> Self {
+        CubieClassification {
+            centroids,
+            facelets,
+        }
+    }
+
+    /// Returns the cost of each orientation of `piece` when put on `position`.
+    /// Both are given as their facelets in the solved cube. Orientation `o` puts the k-th colour of the piece on the facelet `(o + k) % n` of the position.
+    fn orientation_costs(&self, position: &[usize], piece: &[usize]) -> Vec<f64> {
+        let n = position.len();
+        (0..n)
+            .map(|ori| {
+                (0..n)
+                    .map(|k| {
+                        self.facelets[position[(ori + k) % n]]
+                            .distance_to(&self.centroids[piece[k] / 9])
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Places every piece of a kind (corners or edges) and writes their colours in the notation
+    fn place_pieces<const N: usize>(&self, pieces: &[[usize; N]], notation: &mut [char]) {
+        let costs = pieces
+            .iter()
+            .map(|position| {
+                pieces
+                    .iter()
+                    .map(|piece| {
+                        self.orientation_costs(position, piece)
+                            .into_iter()
+                            .fold(f64::INFINITY, f64::min)
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
+        let assignment = solve_assignment(&costs);
+        for (position, piece) in pieces.iter().zip(assignment) {
+            let piece = &pieces[piece];
+            let (ori, _) = self
+                .orientation_costs(position, piece)
+                .into_iter()
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            for k in 0..N {
+                notation[position[(ori + k) % N]] = FACES[piece[k] / 9];
+            }
+        }
+    }
+
+    /// Returns the notation of the closest cube made of existing pieces
+    pub fn classify(&self) -> String {
+        let mut notation = vec![' '; 54];
+        for (i, face) in FACES.iter().enumerate() {
+            notation[9 * i + 4] = *face;
+        }
+        self.place_pieces(&CORNER_FACELET, &mut notation);
+        self.place_pieces(&EDGE_FACELET, &mut notation);
+        notation.iter().collect()
+    }
+}
+
+/// Maximum number of k-means iterations, reached only if the assignment keeps oscillating
+const MAX_CLUSTERING_ITERATIONS: usize = 100;
+
+/// Size-constrained k-means classification that does not trust the centre readings.
+/// Edge and corner facelets are clustered into 6 groups of 4 edges and 4 corners each.
+/// The assignment step is solved as an assignment problem so that every cluster keeps this size.
+/// Clustering starts both from the most spread out facelets and from the centres, and the tightest result is kept.
+/// Centres are then only used to name the clusters, except the ones marked as unreliable which are named by elimination.
+pub struct ClusterClassification {
+    /// Scanned facelets, in the order of the standard notation
+    facelets: Vec<FeaturePoint>,
+    /// Faces whose centre reading should not be used to name the clusters
+    unreliable_centres: Vec<char>,
+}
+
+impl ClusterClassification {
+    pub fn init(facelets: Vec<FeaturePoint>, unreliable_centres: Vec<char>) -> Self {
+        ClusterClassification {
+            facelets,
+            unreliable_centres,
+        }
+    }
+
+    /// Returns the mean of the given points
+    fn centroid(points: &[&FeaturePoint]) -> FeaturePoint {
+        let sum = points.iter().fold([0.; 4], |acc, x| {
+            [acc[0] + x.r, acc[1] + x.g, acc[2] + x.b, acc[3] + x.l]
+        });
+        let n = points.len() as f64;
+        FeaturePoint {
+            r: sum[0] / n,
+            g: sum[1] / n,
+            b: sum[2] / n,
+            l: sum[3] / n,
+            index: 0,
+        }
+    }
+
+    /// Assigns the points to the centroids so that each centroid gets the same number of points.
+    /// Returns the centroid of every point.
+    fn assign(points: &[FeaturePoint], centroids: &[FeaturePoint]) -> Vec<usize> {
+        let slots = points.len() / centroids.len();
+        let costs = points
+            .iter()
+            .map(|point| {
+                (0..points.len())
+                    .map(|slot| point.distance_to(&centroids[slot / slots]).powi(2))
+                    .collect_vec()
+            })
+            .collect_vec();
+        solve_assignment(&costs)
+            .into_iter()
+            .map(|slot| slot / slots)
+            .collect()
+    }
+
+    /// Picks the most spread out points as initial centroids
+    fn farthest_points(points: &[FeaturePoint]) -> Vec<FeaturePoint> {
+        let mean = Self::centroid(&points.iter().collect_vec());
+        let mut seeds = vec![*points
+            .iter()
+            .max_by(|a, b| a.distance_to(&mean).total_cmp(&b.distance_to(&mean)))
+            .unwrap()];
+        while seeds.len() < FACES.len() {
+            let min_distance = |x: &FeaturePoint| {
+                seeds
+                    .iter()
+                    .map(|s| x.distance_to(s))
+                    .fold(f64::INFINITY, f64::min)
+            };
+            let next = *points
+                .iter()
+                .max_by(|a, b| min_distance(a).total_cmp(&min_distance(b)))
+                .unwrap();
+            seeds.push(next);
+        }
+        seeds
+    }
+
+    /// Runs k-means on the edges and corners from the given centroids.
+    /// Returns the cluster of every edge and corner, the final centroids and the inertia of the clustering.
+    fn run(
+        edges: &[FeaturePoint],
+        corners: &[FeaturePoint],
+        mut centroids: Vec<FeaturePoint>,
+    ) -> (Vec<usize>, Vec<FeaturePoint>, f64) {
+        let points = edges.iter().chain(corners).collect_vec();
+        let mut clusters = vec![];
+        for _ in 0..MAX_CLUSTERING_ITERATIONS {
+            let mut assigned = Self::assign(edges, &centroids);
+            assigned.extend(Self::assign(corners, &centroids));
+            if assigned == clusters {
+                break;
+            }
+            clusters = assigned;
+            centroids = (0..FACES.len())
+                .map(|c| {
+                    let members = points
+                        .iter()
+                        .zip(&clusters)
+                        .filter(|(_, cluster)| **cluster == c)
+                        .map(|(point, _)| *point)
+                        .collect_vec();
+                    Self::centroid(&members)
+                })
+                .collect();
+        }
+        let inertia = points
+            .iter()
+            .zip(&clusters)
+            .map(|(point, cluster)| point.distance_to(&centroids[*cluster]).powi(2))
+            .sum();
+        (clusters, centroids, inertia)
+    }
+
+    /// Returns the notation given by the tightest clustering
+    pub fn classify(&self) -> String {
+        let edges = self
+            .facelets
+            .iter()
+            .filter(|x| SIDE_INDICES.contains(&x.index))
+            .copied()
+            .collect_vec();
+        let corners = self
+            .facelets
+            .iter()
+            .filter(|x| !SIDE_INDICES.contains(&x.index) && !CENTRE_INDICES.contains(&x.index))
+            .copied()
+            .collect_vec();
+        let points = edges.iter().chain(&corners).copied().collect_vec();
+        let centres = (0..FACES.len())
+            .map(|i| self.facelets[9 * i + 4])
+            .collect_vec();
+        let (clusters, centroids, _) = [Self::farthest_points(&points), centres.clone()]
+            .into_iter()
+            .map(|seeds| Self::run(&edges, &corners, seeds))
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .unwrap();
+        // name the clusters after the closest reliable centres
+        let costs = centroids
+            .iter()
+            .map(|centroid| {
+                FACES
+                    .iter()
+                    .zip(&centres)
+                    .map(|(face, centre)| {
+                        if self.unreliable_centres.contains(face) {
+                            0.
+                        } else {
+                            centroid.distance_to(centre)
+                        }
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
+        let names = solve_assignment(&costs);
+        let mut notation = vec![' '; 54];
+        for (i, face) in FACES.iter().enumerate() {
+            notation[9 * i + 4] = *face;
+        }
+        for (point, cluster) in points.iter().zip(clusters) {
+            notation[point.index] = FACES[names[cluster]];
+        }
+        notation.iter().collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::classification::{Classification, ColorPoint};
+    use std::collections::HashSet;
+
+    use itertools::Itertools;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
-    pub fn rand_cloud(k: usize, bound: f64) -> Vec<ColorPoint> {
+    use crate::classification::{
+        least_confident, Classification, ClusterClassification, CubieClassification, FeaturePoint,
+    };
+    use crate::constants::{get_corner_colors, get_edge_colors, CORNER_FACELET, EDGE_FACELET};
+
+    pub fn rand_cloud(rng: &mut impl Rng, k: usize, bound: f64) -> Vec<FeaturePoint> {
         let mut res = vec![];
         for i in 0..k {
             let (x, y, z) = (
-                rand::random::<f64>() % bound,
-                rand::random::<f64>() % bound,
-                rand::random::<f64>() % bound,
+                rng.gen::<f64>() % bound,
+                rng.gen::<f64>() % bound,
+                rng.gen::<f64>() % bound,
             );
-            res.push(ColorPoint {
+            res.push(FeaturePoint {
                 r: x,
                 g: y,
                 b: z,
+                l: 0.,
                 index: i,
             });
         }
@@ -117,7 +575,7 @@ mod tests {
 
     #[test]
     fn test_classify() {
-        let cloud = rand_cloud(54, 100.);
+        let cloud = rand_cloud(&mut StdRng::seed_from_u64(0), 54, 100.);
         let (rp, bp) = cloud.split_at(6);
         let mut clas = Classification::init(Vec::from(rp), Vec::from(bp));
         let res = clas.classify();
@@ -125,4 +583,85 @@ mod tests {
             assert_eq!(result.1.len(), 8)
         }
     }
+
+    #[test]
+    fn test_cubie_classify() {
+        let cloud = rand_cloud(&mut StdRng::seed_from_u64(0), 60, 100.);
+        let (centroids, facelets) = cloud.split_at(6);
+        let facelets = facelets
+            .iter()
+            .enumerate()
+            .map(|(i, x)| FeaturePoint { index: i, ..*x })
+            .collect_vec();
+        let notation = CubieClassification::init(Vec::from(centroids), facelets)
+            .classify()
+            .chars()
+            .collect_vec();
+        let corners = CORNER_FACELET
+            .iter()
+            .map(|x| x.iter().map(|i| notation[*i]).collect::<HashSet<char>>())
+            .collect_vec();
+        for colors in get_corner_colors() {
+            assert_eq!(corners.iter().filter(|x| **x == colors).count(), 1);
+        }
+        let edges = EDGE_FACELET
+            .iter()
+            .map(|x| x.iter().map(|i| notation[*i]).collect::<HashSet<char>>())
+            .collect_vec();
+        for colors in get_edge_colors() {
+            assert_eq!(edges.iter().filter(|x| **x == colors).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_cluster_classify_with_bad_centre() {
+        let truth = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        let colors = [
+            [1., 1., 1.],
+            [0., 0., 1.],
+            [1., 0., 0.],
+            [1., 1., 0.],
+            [0., 1., 0.],
+            [1., 0.5, 0.],
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut facelets = (0..54)
+            .map(|index| {
+                let noise = rand_cloud(&mut rng, 1, 1.)[0];
+                let color = colors[index / 9];
+                FeaturePoint {
+                    r: color[0] + noise.r * 0.1,
+                    g: color[1] + noise.g * 0.1,
+                    b: color[2] + noise.b * 0.1,
+                    l: 0.,
+                    index,
+                }
+            })
+            .collect_vec();
+        // the white centre has a logo that reads as yellow
+        facelets[4] = FeaturePoint {
+            index: 4,
+            ..facelets[31]
+        };
+        let notation = ClusterClassification::init(facelets, vec!['U']).classify();
+        assert_eq!(notation, truth);
+    }
+
+    #[test]
+    fn test_least_confident() {
+        let truth = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        let mut facelets = (0..54)
+            .map(|index| FeaturePoint {
+                r: (index / 9) as f64,
+                g: 0.,
+                b: 0.,
+                l: 0.,
+                index,
+            })
+            .collect_vec();
+        // a reading halfway between R and F, then one slightly off
+        facelets[12].r = 1.5;
+        facelets[30].r = 3.2;
+        assert_eq!(least_confident(&facelets, truth, 2), vec![12, 30]);
+    }
 }
@@ -18,6 +18,19 @@ pub const SIDE_INDICES: [usize; 24] = [
 
 pub const CENTRE_INDICES: [usize; 6] = [4, 22, 31, 49, 13, 40];
 
+/// Face letters, in the order they appear in the standard notation
+pub const FACES: [char; 6] = ['U', 'R', 'F', 'D', 'L', 'B'];
+
+/// Colour every face is displayed with, in the order of [FACES]
+pub const FACE_COLOURS: [[u8; 3]; 6] = [
+    [255, 255, 255],
+    [0, 0, 255],
+    [255, 0, 0],
+    [255, 255, 0],
+    [0, 255, 0],
+    [255, 165, 0],
+];
+
 #[rustfmt::skip]
 pub enum Facelet {
     U1, U2, U3, U4, _U5, U6, U7, U8, U9,
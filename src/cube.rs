@@ -1,16 +1,56 @@
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{Read, Write};
-use std::{char, fs, iter};
+use std::{char, fs};
 
 use colored::Colorize;
 use itertools::Itertools;
 use kewb::fs::read_table;
-use kewb::{CubieCube, FaceCube, Solution, Solver};
+use kewb::scramble::scramble_from_str;
+use kewb::{CubieCube, DataTable, FaceCube, Solution, Solver};
 use paris::info;
 
-use crate::classification::{Classification, ColorPoint};
-use crate::constants::SIDE_INDICES;
+use crate::aggregation::Aggregation;
+use crate::classification::{
+    Classification, Classifier, ClusterClassification, ColorPoint, CubieClassification,
+    FeaturePoint, FeatureWeights,
+};
+use crate::constants::{FACES, SIDE_INDICES};
+use crate::diagnosis::{diagnose, Violation};
+use crate::scan_file::{FaceletScan, ReadingUnit, ScanFile, ScanMetadata, SCAN_FORMAT_VERSION};
+use crate::state_format::StateFormat;
+
+/// Reasons why a cube state given by the user cannot be solved
+#[derive(Debug)]
+pub enum StateError {
+    /// The notation does not have 54 facelets
+    Length(usize),
+    /// The notation holds a letter that is not a face, or a colour of the scheme
+    UnknownColour(char),
+    /// The centre of a face does not hold the colour of the face
+    Centre { face: char, found: char },
+    /// The notation does not describe a valid cube
+    Invalid(Vec<Violation>),
+    /// The scramble holds an unknown move
+    Scramble(String),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::Length(length) => write!(f, "{length} facelets given instead of 54"),
+            StateError::UnknownColour(colour) => write!(f, "{colour} is not a face colour"),
+            StateError::Centre { face, found } => {
+                write!(f, "the centre of {face} is {found} instead of {face}")
+            }
+            StateError::Invalid(violations) => write!(f, "{}", violations.iter().join(", ")),
+            StateError::Scramble(scramble) => write!(f, "{scramble} is not a valid scramble"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
 
 /// Represents the cube faces and state
 pub struct Cube {
@@ -18,6 +58,18 @@ pub struct Cube {
     pub curr_idx: usize,
     /// Stores RGB values in the order of the standard notation
     pub facelet_rgb_values: Vec<ColorPoint>,
+    /// Unit of the RGB values
+    pub unit: ReadingUnit,
+    /// Weights of the features computed from the RGB values for classification
+    pub feature_weights: FeatureWeights,
+    /// Faces whose centre reading should not be trusted, for instance because of a logo
+    pub unreliable_centres: Vec<char>,
+    /// Raw color sensor samples of every facelet, in the order of the standard notation. Empty if unknown
+    pub raw_samples: Vec<Vec<[f64; 3]>>,
+    /// Settings and context of the scan, saved along with it
+    pub metadata: ScanMetadata,
+    /// True notation of the scanned cube, if known
+    pub truth: Option<String>,
     /// Faces that can be accessed by simply flipping. First one is the one currently down
     pub next_faces: [char; 4],
     /// right from the sensor POV
@@ -30,24 +82,51 @@ impl Cube {
     pub fn init() -> Self {
         Self {
             curr_idx: 0,
-            facelet_rgb_values: iter::repeat(ColorPoint {
-                r: 0.,
-                g: 0.,
-                b: 0.,
-                index: 0,
-            })
-            .take(54)
-            .collect(),
+            facelet_rgb_values: vec![
+                ColorPoint {
+                    r: 0.,
+                    g: 0.,
+                    b: 0.,
+                    index: 0,
+                };
+                54
+            ],
+            unit: ReadingUnit::Raw,
+            feature_weights: FeatureWeights::default(),
+            unreliable_centres: vec![],
+            raw_samples: vec![vec![]; 54],
+            metadata: ScanMetadata::default(),
+            truth: None,
             next_faces: ['R', 'D', 'L', 'U'],
             right_face: 'B',
             left_face: 'F',
         }
     }
 
+    /// Returns the points classification works on, in the order of the standard notation.
+    /// Their chromaticity is kept apart from their luminance, which is relative to the median luminance of the cube
+    /// so that it does not depend on the sensor gain. Normalised readings have no luminance.
+    pub fn feature_points(&self) -> Vec<FeaturePoint> {
+        let norms = self
+            .facelet_rgb_values
+            .iter()
+            .map(|x| x.to_array().iter().map(|x| x.powi(2)).sum::<f64>().sqrt())
+            .sorted_by(f64::total_cmp)
+            .collect_vec();
+        let reference = match self.unit {
+            ReadingUnit::Raw if norms[27] > 0. => Some(norms[27]),
+            ReadingUnit::Raw => Some(1.),
+            ReadingUnit::Normalised => None,
+        };
+        self.facelet_rgb_values
+            .iter()
+            .map(|x| FeaturePoint::from_reading(x, reference, self.feature_weights))
+            .collect()
+    }
+
     /// Converts the cube into the standard notation. A solved cube would be UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB
     pub fn to_notation(&self) -> String {
-        // we clone so that a function named to_something doesn't have side effects
-        let facelets = self.facelet_rgb_values.clone();
+        let facelets = self.feature_points();
         let mut centres = vec![]; // centroids (red points)
         let mut sides = vec![]; // points to classify (black points)
         let mut corners = vec![];
@@ -62,15 +141,15 @@ impl Cube {
         let centre_index = centre_to_face.keys();
         for centre in centre_index.clone() {
             let face = facelets.get(*centre).unwrap();
-            centres.push(face.clone());
+            centres.push(*face);
         }
         for side in 0..54 {
             if !centre_index.clone().any(|x| x == &side) {
                 let face = facelets.get(side).unwrap();
                 if SIDE_INDICES.contains(&face.index) {
-                    sides.push(face.clone());
+                    sides.push(*face);
                 } else {
-                    corners.push(face.clone());
+                    corners.push(*face);
                 }
             }
         }
@@ -78,10 +157,10 @@ impl Cube {
         let res_sides = classification_side.classify();
         let mut classification_corners = Classification::init(centres, corners);
         let res_corners = classification_corners.classify();
-        let mut string: Vec<char> = iter::repeat(' ').take(54).collect();
+        let mut string: Vec<char> = vec![' '; 54];
         for res in [res_sides, res_corners] {
             for class in res {
-                let face_char = centre_to_face.get(&class.0.index).unwrap().clone();
+                let face_char = *centre_to_face.get(&class.0.index).unwrap();
                 string[class.0.index] = face_char;
                 for point in class.1 {
                     string[point.1.index] = face_char;
@@ -91,6 +170,64 @@ impl Cube {
         string.iter().collect()
     }
 
+    /// Converts the cube into the standard notation by assigning whole pieces instead of single facelets.
+    /// Unlike [Cube::to_notation], the result never contains nonexistent or duplicated pieces.
+    pub fn to_notation_cubies(&self) -> String {
+        let facelets = self.feature_points();
+        let centroids = (0..FACES.len()).map(|i| facelets[9 * i + 4]).collect_vec();
+        CubieClassification::init(centroids, facelets).classify()
+    }
+
+    /// Converts the cube into the standard notation by clustering the facelets without trusting the centres.
+    /// Centres listed in `unreliable_centres` are not even used to name the clusters.
+    pub fn to_notation_clusters(&self) -> String {
+        ClusterClassification::init(self.feature_points(), self.unreliable_centres.clone())
+            .classify()
+    }
+
+    /// Converts the cube into the standard notation using the given classifier
+    pub fn to_notation_with(&self, classifier: Classifier) -> String {
+        match classifier {
+            Classifier::Greedy => self.to_notation(),
+            Classifier::Cubie => self.to_notation_cubies(),
+            Classifier::Cluster => self.to_notation_clusters(),
+        }
+    }
+
+    /// Checks a notation typed by the user, such as a state read from an app, and returns it in upper case
+    pub fn parse_state(state: &str) -> Result<String, StateError> {
+        let notation = state.trim().to_uppercase();
+        let chars = notation.chars().collect_vec();
+        if chars.len() != 54 {
+            return Err(StateError::Length(chars.len()));
+        }
+        if let Some(colour) = chars.iter().find(|x| !FACES.contains(x)) {
+            return Err(StateError::UnknownColour(*colour));
+        }
+        for (i, face) in FACES.iter().enumerate() {
+            if chars[9 * i + 4] != *face {
+                return Err(StateError::Centre {
+                    face: *face,
+                    found: chars[9 * i + 4],
+                });
+            }
+        }
+        let violations = diagnose(&notation);
+        if !violations.is_empty() {
+            return Err(StateError::Invalid(violations));
+        }
+        Ok(notation)
+    }
+
+    /// Returns the notation of a solved cube once the scramble is applied, such as `R U R' U2`
+    pub fn scrambled(scramble: &str) -> Result<String, StateError> {
+        let moves =
+            scramble_from_str(scramble).map_err(|_| StateError::Scramble(scramble.to_string()))?;
+        let state = CubieCube::from(&moves);
+        let face_cube = FaceCube::try_from(&state).expect("Scrambled cube is always valid");
+        Ok(face_cube.to_string())
+    }
+
     /// takes a notation and returns a solution
     pub fn solve(notation: String) -> Solution {
         let table = read_table("./cache_file").unwrap();
@@ -101,68 +238,126 @@ impl Cube {
         solver.solve(state).expect("Could not solve cube")
     }
 
-    /// Saves the scan to file.
+    /// Solves the notation with an already loaded table, or returns None if it does not describe a valid cube
+    pub fn try_solve(table: &DataTable, notation: &str) -> Option<Solution> {
+        let face_cube = FaceCube::try_from(notation).ok()?;
+        let state = CubieCube::try_from(&face_cube).ok()?;
+        Solver::new(table, 30, None).solve(state)
+    }
+
+    /// Reduces the raw samples of a facelet into its RGB value
+    pub fn aggregate_facelet(&mut self, idx: usize, aggregation: Aggregation) {
+        let rgb = aggregation.aggregate(&self.raw_samples[idx]);
+        self.facelet_rgb_values[idx] = ColorPoint {
+            r: rgb[0],
+            g: rgb[1],
+            b: rgb[2],
+            index: idx,
+        };
+    }
+
+    /// Recomputes the RGB value of every facelet that has raw samples.
+    /// Used to apply another aggregation method to previous scans.
+    /// Raw samples are always in [ReadingUnit::Raw], so readings of another unit, such as the ones of legacy scan files,
+    /// are only recomputed if every facelet has samples, and the scan then holds raw readings.
+    pub fn reaggregate(&mut self, aggregation: Aggregation) -> std::io::Result<()> {
+        if self.unit != ReadingUnit::Raw {
+            if self.raw_samples.iter().any(Vec::is_empty) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Cannot recompute {} readings without the raw samples of every facelet",
+                        self.unit
+                    ),
+                ));
+            }
+            self.unit = ReadingUnit::Raw;
+        }
+        for idx in 0..54 {
+            if !self.raw_samples[idx].is_empty() {
+                self.aggregate_facelet(idx, aggregation);
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the scan to a new file of the `scans` directory, named after the current date
     pub fn export(&self) {
         fs::create_dir_all("scans").ok();
-        let mut file = File::create(format!(
-            "scans/{}",
-            chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S")
-        ))
-        .unwrap();
-        let mut string = String::new();
-        for point in self.facelet_rgb_values.iter().map(ColorPoint::to_array) {
-            string.push_str(format!("{}, {}, {}\n", point[0], point[1], point[2]).as_str())
-        }
-        file.write_all(&format!("{}", string).into_bytes()).unwrap();
+        let file_path = format!("scans/{}", chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S"));
+        self.save(&file_path).unwrap();
         info!("Saved scan to file");
     }
 
-    /// Imports a scan from file. Used for debugging
+    /// Saves the scan to a versioned JSON file, along with its raw samples, metadata and true notation
+    pub fn save(&self, file_path: &str) -> std::io::Result<()> {
+        let scan = ScanFile {
+            version: SCAN_FORMAT_VERSION,
+            metadata: self.metadata.clone(),
+            truth: self.truth.clone(),
+            unit: self.unit,
+            facelets: self
+                .facelet_rgb_values
+                .iter()
+                .zip(&self.raw_samples)
+                .map(|(point, samples)| FaceletScan {
+                    rgb: point.to_array(),
+                    samples: samples.clone(),
+                })
+                .collect(),
+        };
+        let mut file = File::create(file_path)?;
+        file.write_all(serde_json::to_string_pretty(&scan)?.as_bytes())
+    }
+
+    /// Imports a scan from file. Used for debugging.
+    /// Reads both versioned JSON files and legacy files, described in [ScanFile::parse_legacy].
     pub fn import(&mut self, file_path: String) -> std::io::Result<()> {
         let mut file = File::open(file_path)?;
         let mut output = String::new();
         file.read_to_string(&mut output)?;
-        for (pos, line) in output.split('\n').enumerate() {
-            if line.trim() == "" {
-                continue;
-            }
-            let rgb: Vec<f64> = line
-                .split(", ")
-                .map(str::parse::<f64>)
-                .map(Result::unwrap)
-                .collect();
+        if ScanFile::is_versioned(&output) {
+            let scan = ScanFile::parse(&output)?;
+            self.metadata = scan.metadata;
+            self.truth = scan.truth;
+            self.unit = scan.unit;
+            self.set_facelets(scan.facelets);
+        } else {
+            let facelets = ScanFile::parse_legacy(&output)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            self.unit = ReadingUnit::Normalised;
+            self.set_facelets(facelets);
+        }
+        Ok(())
+    }
+
+    /// Sets the readings and raw samples of every facelet, given in the order of the standard notation
+    fn set_facelets(&mut self, facelets: Vec<FaceletScan>) {
+        for (pos, facelet) in facelets.into_iter().enumerate() {
             self.facelet_rgb_values[pos] = ColorPoint {
-                r: rgb[0],
-                g: rgb[1],
-                b: rgb[2],
+                r: facelet.rgb[0],
+                g: facelet.rgb[1],
+                b: facelet.rgb[2],
                 index: pos,
             };
+            self.raw_samples[pos] = facelet.samples;
         }
-        info!("Loaded scan from file");
-        Ok(())
     }
 
-    /// Prints the colored patron of the cube in the stdout
-    pub fn print_graphical(nota: &str) {
-        fn print_letter(idx: usize, chars: &Vec<char>) {
-            let colors: HashMap<char, [u8; 3]> = HashMap::from([
-                ('U', [255, 255, 255]),
-                ('R', [0, 0, 255]),
-                ('F', [255, 0, 0]),
-                ('D', [255, 255, 0]),
-                ('L', [0, 255, 0]),
-                ('B', [255, 165, 0]),
-            ]);
+    /// Prints the colored patron of the cube in the stdout, with the letters and colours of the format
+    pub fn print_graphical(nota: &str, format: &StateFormat) {
+        let print_letter = |idx: usize, chars: &[char]| {
             let letter = chars[idx];
-            let color = colors.get(&letter).unwrap();
+            let color = format.colours()[FACES.iter().position(|x| *x == letter).unwrap()];
             print!(
                 "{}",
-                letter
+                format
+                    .letter(letter)
                     .to_string()
                     .as_str()
                     .truecolor(color[0], color[1], color[2])
             );
-        }
+        };
 
         let chars = nota.chars().collect_vec();
         // up
@@ -204,3 +399,65 @@ impl Cube {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregation::Aggregation;
+    use crate::cube::{Cube, StateError};
+    use crate::diagnosis::Violation;
+    use crate::scan_file::ReadingUnit;
+
+    #[test]
+    fn test_given_states() {
+        let solved = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        assert_eq!(Cube::scrambled("").unwrap(), solved);
+        assert_eq!(Cube::scrambled("R U R' U' U R U' R'").unwrap(), solved);
+        let scrambled = Cube::scrambled("R U2 F' D B2 L").unwrap();
+        assert_ne!(scrambled, solved);
+        assert_eq!(
+            Cube::parse_state(&scrambled.to_lowercase()).unwrap(),
+            scrambled
+        );
+        assert!(matches!(
+            Cube::scrambled("R X"),
+            Err(StateError::Scramble(_))
+        ));
+        assert!(matches!(
+            Cube::parse_state(&solved[1..]),
+            Err(StateError::Length(53))
+        ));
+        let swapped = format!("{}R{}", &solved[..4], &solved[5..]);
+        assert!(matches!(
+            Cube::parse_state(&swapped),
+            Err(StateError::Centre {
+                face: 'U',
+                found: 'R'
+            })
+        ));
+        // twist the UFR corner in place: its U9, R1 and F3 facelets take the colours of R1, F3 and U9
+        let mut twisted = solved.chars().collect::<Vec<_>>();
+        twisted[8] = 'R';
+        twisted[9] = 'F';
+        twisted[20] = 'U';
+        let twisted: String = twisted.iter().collect();
+        assert!(matches!(
+            Cube::parse_state(&twisted),
+            Err(StateError::Invalid(violations))
+                if violations == [Violation::CornerTwist { sum: 2 }]
+        ));
+    }
+
+    #[test]
+    fn reaggregating_legacy_readings_gives_raw_readings() {
+        let mut cube = Cube::init();
+        cube.unit = ReadingUnit::Normalised;
+        cube.raw_samples[0] = vec![[100., 200., 300.]];
+        assert!(cube.reaggregate(Aggregation::default()).is_err());
+        assert_eq!(cube.unit, ReadingUnit::Normalised);
+
+        cube.raw_samples = vec![vec![[100., 200., 300.]]; 54];
+        cube.reaggregate(Aggregation::default()).unwrap();
+        assert_eq!(cube.unit, ReadingUnit::Raw);
+        assert_eq!(cube.facelet_rgb_values[53].to_array(), [100., 200., 300.]);
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::batch::{csv_field, optional, BatchFormat};
+use crate::bench::Pipeline;
+use crate::calibration::PositionClass;
+use crate::constants::FACES;
+
+/// Readings and labels of a facelet of a scan file.
+/// A scan file that cannot be loaded gives a single row holding only the file and the error
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct DatasetRow {
+    pub file: String,
+    /// Index of the facelet in the standard notation
+    pub facelet: Option<usize>,
+    /// Face the facelet belongs to
+    pub face: Option<char>,
+    pub position: Option<PositionClass>,
+    /// Raw color sensor samples. Empty if the scan file does not record them
+    pub samples: Vec<[f64; 3]>,
+    /// Aggregated reading
+    pub r: Option<f64>,
+    pub g: Option<f64>,
+    pub b: Option<f64>,
+    /// Aggregated reading corrected by the calibration. Empty without a calibration
+    pub corrected_r: Option<f64>,
+    pub corrected_g: Option<f64>,
+    pub corrected_b: Option<f64>,
+    /// Colour given by the classifier
+    pub predicted: Option<char>,
+    /// Colour after fixing, if a valid notation was found
+    pub fixed: Option<char>,
+    /// True colour, if known
+    pub truth: Option<char>,
+    /// Reason why the file could not be loaded or fixed
+    pub error: Option<String>,
+}
+
+/// Names of the columns of the CSV format, in the order of the fields of [DatasetRow]
+const CSV_HEADER: [&str; 15] = [
+    "file",
+    "facelet",
+    "face",
+    "position",
+    "samples",
+    "r",
+    "g",
+    "b",
+    "corrected_r",
+    "corrected_g",
+    "corrected_b",
+    "predicted",
+    "fixed",
+    "truth",
+    "error",
+];
+
+impl DatasetRow {
+    /// Writes the row as a CSV line, the samples being written as a JSON array and missing values left empty
+    pub fn to_csv(&self) -> String {
+        [
+            self.file.clone(),
+            optional(&self.facelet),
+            optional(&self.face),
+            optional(&self.position),
+            serde_json::to_string(&self.samples).unwrap(),
+            optional(&self.r),
+            optional(&self.g),
+            optional(&self.b),
+            optional(&self.corrected_r),
+            optional(&self.corrected_g),
+            optional(&self.corrected_b),
+            optional(&self.predicted),
+            optional(&self.fixed),
+            optional(&self.truth),
+            optional(&self.error),
+        ]
+        .map(csv_field)
+        .join(",")
+    }
+}
+
+/// Classifies and fixes a scan file and returns a row for each of its facelets.
+/// `truth` is used if the file does not record its true notation.
+/// Failures are recorded as described in [Pipeline]
+pub fn rows(pipeline: &Pipeline, file: &str, truth: Option<&str>) -> Vec<DatasetRow> {
+    let failed = |error: String| {
+        vec![DatasetRow {
+            file: file.to_string(),
+            error: Some(error),
+            ..Default::default()
+        }]
+    };
+    let mut cube = match pipeline.read(file) {
+        Ok(cube) => cube,
+        Err(e) => return failed(e),
+    };
+    let aggregated = cube.facelet_rgb_values.clone();
+    if let Err(e) = pipeline.correct(&mut cube) {
+        return failed(e);
+    }
+    let corrected = pipeline
+        .calibration
+        .as_ref()
+        .map(|_| &cube.facelet_rgb_values);
+    let predicted = cube.to_notation_with(pipeline.classifier);
+    let (fixed, error) = match pipeline.fix(&cube, predicted.clone()) {
+        Ok(report) => (Some(report.fixed), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let truth = cube.truth.as_deref().or(truth);
+    let label = |notation: Option<&str>, i: usize| notation.and_then(|x| x.chars().nth(i));
+    (0..54)
+        .map(|i| {
+            let reading = &aggregated[i];
+            let corrected = corrected.map(|x| &x[i]);
+            DatasetRow {
+                file: file.to_string(),
+                facelet: Some(i),
+                face: Some(FACES[i / 9]),
+                position: Some(PositionClass::of(i)),
+                samples: cube.raw_samples[i].clone(),
+                r: Some(reading.r),
+                g: Some(reading.g),
+                b: Some(reading.b),
+                corrected_r: corrected.map(|x| x.r),
+                corrected_g: corrected.map(|x| x.g),
+                corrected_b: corrected.map(|x| x.b),
+                predicted: label(Some(&predicted), i),
+                fixed: label(fixed.as_deref(), i),
+                truth: label(truth, i),
+                error: error.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Writes a row for every facelet of the scan files, as soon as each file is processed
+pub fn export_dataset(
+    pipeline: &Pipeline,
+    files: &[String],
+    truth: Option<&str>,
+    format: BatchFormat,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    if format == BatchFormat::Csv {
+        writeln!(output, "{}", CSV_HEADER.join(","))?;
+    }
+    for file in files {
+        for row in rows(pipeline, file, truth) {
+            match format {
+                BatchFormat::Csv => writeln!(output, "{}", row.to_csv())?,
+                BatchFormat::Jsonl => writeln!(output, "{}", serde_json::to_string(&row)?)?,
+            }
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench::Pipeline;
+    use crate::calibration::{Calibration, PositionClass};
+    use crate::classification::Classifier;
+    use crate::dataset::{rows, DatasetRow, CSV_HEADER};
+    use crate::fixer::Fixer;
+
+    #[test]
+    fn test_dataset_rows() {
+        let row = DatasetRow {
+            file: "scans/a".to_string(),
+            facelet: Some(12),
+            face: Some('R'),
+            position: Some(PositionClass::Edge),
+            samples: vec![[1., 2., 3.], [4., 5., 6.5]],
+            r: Some(2.5),
+            g: Some(3.5),
+            b: Some(4.75),
+            corrected_r: Some(2.25),
+            corrected_g: None,
+            corrected_b: None,
+            predicted: Some('F'),
+            fixed: Some('R'),
+            truth: None,
+            error: None,
+        };
+        assert_eq!(
+            row.to_csv(),
+            "scans/a,12,R,edge,\"[[1.0,2.0,3.0],[4.0,5.0,6.5]]\",2.5,3.5,4.75,2.25,,,F,R,,"
+        );
+        let json = serde_json::to_value(&row).unwrap();
+        assert_eq!(json["position"], "edge");
+        assert_eq!(json["samples"][1][2], 6.5);
+        let mut keys = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        let mut header = CSV_HEADER.map(String::from).to_vec();
+        header.sort();
+        assert_eq!(keys, header);
+    }
+
+    #[test]
+    fn test_rows_of_scan_file() {
+        let pipeline = Pipeline {
+            classifier: Classifier::Cubie,
+            fixer: Fixer::Assignment,
+            ..Default::default()
+        };
+        let file = "scan_test_files/solvable.txt";
+        let unlabelled = rows(&pipeline, file, None);
+        assert_eq!(unlabelled.len(), 54);
+        for (i, row) in unlabelled.iter().enumerate() {
+            assert_eq!(row.facelet, Some(i));
+            assert_eq!(row.error, None);
+            assert_eq!(row.truth, None);
+            assert!(row.fixed.is_some());
+            // the legacy scan file has no raw samples
+            assert!(row.samples.is_empty());
+            // no calibration, so nothing is corrected
+            assert_eq!(row.corrected_r, None);
+        }
+        assert_eq!(unlabelled[4].position, Some(PositionClass::Centre));
+        assert_eq!(unlabelled[4].predicted, Some('U'));
+
+        let truth = unlabelled
+            .iter()
+            .map(|x| x.fixed.unwrap())
+            .collect::<String>();
+        let labelled = rows(&pipeline, file, Some(&truth));
+        assert!(labelled.iter().all(|x| x.truth == x.fixed));
+        assert_eq!(labelled[0].r, unlabelled[0].r);
+
+        // the calibration only changes the corrected columns
+        let calibrated = Pipeline {
+            calibration: Some(Calibration {
+                offsets: vec![[1., 2., 3.]; 54],
+                ..Default::default()
+            }),
+            ..pipeline
+        };
+        let corrected = rows(&calibrated, file, None);
+        assert_eq!(corrected[0].r, unlabelled[0].r);
+        assert_eq!(corrected[0].corrected_r, unlabelled[0].r.map(|x| x - 1.));
+        assert_eq!(corrected[0].corrected_b, unlabelled[0].b.map(|x| x - 3.));
+    }
+}
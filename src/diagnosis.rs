@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::Instant;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::classification::{colour_centroids, FeaturePoint};
+use crate::constants::{CENTRE_INDICES, CORNER_FACELET, EDGE_FACELET, FACES, SIDE_INDICES};
+use crate::fixer::{calculate_score, FixerConfig};
+
+/// Maximum number of repair steps before giving up
+const MAX_REPAIR_STEPS: usize = 12;
+/// Number of repairs of a notation whose score is actually computed
+const REPAIRS_PER_STEP: usize = 6;
+/// Number of notations kept between two repair steps
+const BEAM_WIDTH: usize = 3;
+
+/// A reason why a notation does not describe a valid cube.
+/// Violations are listed in the order `CubieCube::try_from` would run into them.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// A colour does not appear exactly 9 times
+    ColourCount { colour: char, count: usize },
+    /// The colours of a piece do not match any existing piece
+    NonexistentPiece { facelets: Vec<usize> },
+    /// The same piece appears on several positions, given by all their facelets
+    DuplicatePiece { facelets: Vec<usize> },
+    /// The sum of the corner twists is not a multiple of 3
+    CornerTwist { sum: usize },
+    /// The sum of the edge flips is odd
+    EdgeFlip { sum: usize },
+    /// The corner and edge permutations do not have the same parity
+    Parity,
+}
+
+impl Violation {
+    /// Returns the facelets that may have to change to fix the violation
+    pub fn implicated(&self, notation: &[char]) -> Vec<usize> {
+        match self {
+            Violation::ColourCount { colour, .. } => (0..54)
+                .filter(|i| notation[*i] == *colour && !CENTRE_INDICES.contains(i))
+                .collect(),
+            Violation::NonexistentPiece { facelets } | Violation::DuplicatePiece { facelets } => {
+                facelets.clone()
+            }
+            Violation::CornerTwist { .. } => CORNER_FACELET.concat(),
+            Violation::EdgeFlip { .. } => EDGE_FACELET.concat(),
+            Violation::Parity => [CORNER_FACELET.concat(), EDGE_FACELET.concat()].concat(),
+        }
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::ColourCount { colour, count } => {
+                write!(f, "{colour} appears {count} times instead of 9")
+            }
+            Violation::NonexistentPiece { facelets } => {
+                write!(f, "facelets {facelets:?} do not form an existing piece")
+            }
+            Violation::DuplicatePiece { facelets } => {
+                write!(f, "facelets {facelets:?} hold the same piece")
+            }
+            Violation::CornerTwist { sum } => {
+                write!(f, "corner twists sum to {sum}, not a multiple of 3")
+            }
+            Violation::EdgeFlip { sum } => write!(f, "edge flips sum to {sum}, which is odd"),
+            Violation::Parity => write!(f, "corner and edge permutations have different parities"),
+        }
+    }
+}
+
+/// Identifies the piece at `position`: returns the piece and its orientation, or None if no piece has these colours.
+/// Orientation `o` means the k-th colour of the piece is on the facelet `(o + k) % n` of the position.
+fn identify<const N: usize>(
+    pieces: &[[usize; N]],
+    notation: &[char],
+    position: usize,
+) -> Option<(usize, usize)> {
+    (0..pieces.len())
+        .cartesian_product(0..N)
+        .find(|(piece, ori)| {
+            (0..N)
+                .all(|k| notation[pieces[position][(ori + k) % N]] == FACES[pieces[*piece][k] / 9])
+        })
+}
+
+/// Checks the pieces of a kind (corners or edges) and returns the piece and orientation of every position.
+/// Returns None if the pieces do not form a permutation.
+fn check_pieces<const N: usize>(
+    pieces: &[[usize; N]],
+    notation: &[char],
+    violations: &mut Vec<Violation>,
+) -> Option<Vec<(usize, usize)>> {
+    let identified = (0..pieces.len())
+        .map(|position| identify(pieces, notation, position))
+        .collect_vec();
+    for (position, piece) in identified.iter().enumerate() {
+        if piece.is_none() {
+            violations.push(Violation::NonexistentPiece {
+                facelets: pieces[position].to_vec(),
+            });
+        }
+    }
+    let positions = identified
+        .iter()
+        .enumerate()
+        .filter_map(|(position, piece)| piece.map(|(piece, _)| (piece, position)))
+        .into_group_map();
+    for (_, positions) in positions.iter().sorted() {
+        if positions.len() > 1 {
+            violations.push(Violation::DuplicatePiece {
+                facelets: positions.iter().flat_map(|p| pieces[*p]).collect(),
+            });
+        }
+    }
+    identified.into_iter().collect()
+}
+
+/// Returns the parity of the permutation of the pieces
+fn parity(pieces: &[(usize, usize)]) -> usize {
+    pieces
+        .iter()
+        .tuple_combinations()
+        .filter(|(a, b)| a.0 > b.0)
+        .count()
+        % 2
+}
+
+/// Lists every reason why the notation does not describe a valid cube. An empty list means it is valid.
+pub fn diagnose(notation: &str) -> Vec<Violation> {
+    let chars = notation.chars().collect_vec();
+    let mut violations = vec![];
+    for colour in FACES {
+        let count = chars.iter().filter(|x| **x == colour).count();
+        if count != 9 {
+            violations.push(Violation::ColourCount { colour, count });
+        }
+    }
+    let corners = check_pieces(&CORNER_FACELET, &chars, &mut violations);
+    let edges = check_pieces(&EDGE_FACELET, &chars, &mut violations);
+    if let Some(corners) = &corners {
+        let sum = corners.iter().map(|x| x.1).sum::<usize>();
+        if sum % 3 != 0 {
+            violations.push(Violation::CornerTwist { sum });
+        }
+    }
+    if let Some(edges) = &edges {
+        let sum = edges.iter().map(|x| x.1).sum::<usize>();
+        if sum % 2 != 0 {
+            violations.push(Violation::EdgeFlip { sum });
+        }
+    }
+    if let (Some(corners), Some(edges)) = (&corners, &edges) {
+        if parity(corners) != parity(edges) {
+            violations.push(Violation::Parity);
+        }
+    }
+    violations
+}
+
+/// Generates the notations fixing the violation by only changing its implicated facelets
+fn repairs(violation: &Violation, chars: &[char]) -> Vec<Vec<char>> {
+    let mut res = vec![];
+    match violation {
+        Violation::ColourCount { count, .. } if *count > 9 => {
+            let missing = FACES
+                .into_iter()
+                .filter(|c| chars.iter().filter(|x| *x == c).count() < 9)
+                .collect_vec();
+            for facelet in violation.implicated(chars) {
+                for colour in &missing {
+                    let mut repaired = chars.to_vec();
+                    repaired[facelet] = *colour;
+                    res.push(repaired);
+                }
+            }
+        }
+        Violation::ColourCount { .. } => {}
+        Violation::NonexistentPiece { .. } | Violation::DuplicatePiece { .. } => {
+            // swap one of the implicated facelets with a facelet of the same kind
+            for a in violation.implicated(chars) {
+                for b in 0..54 {
+                    if chars[a] != chars[b]
+                        && !CENTRE_INDICES.contains(&b)
+                        && SIDE_INDICES.contains(&a) == SIDE_INDICES.contains(&b)
+                    {
+                        let mut repaired = chars.to_vec();
+                        repaired.swap(a, b);
+                        res.push(repaired);
+                    }
+                }
+            }
+        }
+        Violation::CornerTwist { sum } => {
+            // twist a single corner so that the sum becomes a multiple of 3
+            let twist = (3 - sum % 3) % 3;
+            for corner in CORNER_FACELET {
+                let mut repaired = chars.to_vec();
+                for k in 0..3 {
+                    repaired[corner[(k + twist) % 3]] = chars[corner[k]];
+                }
+                res.push(repaired);
+            }
+        }
+        Violation::EdgeFlip { .. } => {
+            for edge in EDGE_FACELET {
+                let mut repaired = chars.to_vec();
+                repaired.swap(edge[0], edge[1]);
+                res.push(repaired);
+            }
+        }
+        Violation::Parity => {
+            // swapping two whole pieces of the same kind changes the parity without touching orientations
+            let corners = CORNER_FACELET.iter().map(|x| x.to_vec()).collect_vec();
+            let edges = EDGE_FACELET.iter().map(|x| x.to_vec()).collect_vec();
+            for pieces in [corners, edges] {
+                for (p, q) in pieces.iter().tuple_combinations() {
+                    let mut repaired = chars.to_vec();
+                    for (a, b) in p.iter().zip(q) {
+                        repaired.swap(*a, *b);
+                    }
+                    if repaired != chars {
+                        res.push(repaired);
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Estimates how much a repair worsens the notation: the sum, over the changed facelets,
+/// of the increase of their distance to the mean of their colour.
+/// Repairs touching the least confident facelets come first.
+fn estimate_cost(
+    rgb_values: &[FeaturePoint],
+    means: &HashMap<char, FeaturePoint>,
+    before: &[char],
+    after: &[char],
+) -> f64 {
+    (0..54)
+        .filter(|i| before[*i] != after[*i])
+        .map(|i| {
+            let distance = |c: char| {
+                means
+                    .get(&c)
+                    .map_or(f64::INFINITY, |m| rgb_values[i].distance_to(m))
+            };
+            distance(after[i]) - distance(before[i])
+        })
+        .sum()
+}
+
+/// Repairs the notation by diagnosing why it is invalid and only changing the implicated facelets.
+/// At each step, the first violation of every kept notation that can be repaired is repaired in every possible way.
+/// A colour appearing less than 9 times has no repair of its own, and is repaired through the colours appearing too often.
+/// Only the repairs with the lowest estimated cost get their score computed.
+/// The notations with the fewest violations are kept for the next step, and the search stops as soon as one is valid.
+/// It also stops early as described in [FixerConfig].
+///
+/// # Arguments
+/// * `rgb_values` - The previously scanned facelet RGB tuples.
+/// * `nota` - The notation string to be repaired.
+/// * `config` - The fixer settings, giving the time budget and the cancellation flag.
+/// * `start` - When the fixer started, to enforce the time budget.
+///
+/// # Returns
+/// The best valid notation found and its score, along with the number of scores computed.
+pub fn targeted_repair(
+    rgb_values: &[FeaturePoint],
+    nota: &str,
+    config: &FixerConfig,
+    start: Instant,
+) -> (Option<(f64, String)>, usize) {
+    let mut evaluations = 1;
+    let mut beam = vec![(calculate_score(rgb_values, nota), nota.to_string())];
+    if diagnose(nota).is_empty() {
+        return (beam.pop(), evaluations);
+    }
+    for _ in 0..MAX_REPAIR_STEPS {
+        if config.should_stop(start) {
+            break;
+        }
+        let mut children = vec![];
+        for (_, notation) in &beam {
+            let chars = notation.chars().collect_vec();
+            let Some(repaired) = diagnose(notation)
+                .iter()
+                .map(|violation| repairs(violation, &chars))
+                .find(|repaired| !repaired.is_empty())
+            else {
+                continue;
+            };
+            let means = colour_centroids(rgb_values, &chars);
+            let candidates = repaired
+                .into_iter()
+                .map(|repaired| {
+                    (
+                        estimate_cost(rgb_values, &means, &chars, &repaired),
+                        repaired,
+                    )
+                })
+                .sorted_by(|a, b| a.0.total_cmp(&b.0))
+                .take(REPAIRS_PER_STEP);
+            for (_, repaired) in candidates {
+                let repaired: String = repaired.iter().collect();
+                evaluations += 1;
+                children.push((calculate_score(rgb_values, &repaired), repaired));
+            }
+        }
+        // notations closer to validity come first, then the ones with the lowest score
+        let children = children
+            .into_iter()
+            .map(|(score, notation)| (diagnose(&notation).len(), score, notation))
+            .sorted_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)))
+            .dedup_by(|a, b| a.2 == b.2)
+            .collect_vec();
+        if let Some((0, score, notation)) = children.first() {
+            return (Some((*score, notation.clone())), evaluations);
+        }
+        if children.is_empty() {
+            break;
+        }
+        beam = children
+            .into_iter()
+            .take(BEAM_WIDTH)
+            .map(|(_, score, notation)| (score, notation))
+            .collect();
+    }
+    (None, evaluations)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use kewb::{CubieCube, FaceCube};
+
+    use crate::cube::Cube;
+    use crate::diagnosis::{diagnose, targeted_repair, Violation};
+    use crate::fixer::FixerConfig;
+
+    const SOLVED: &str = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+
+    fn is_valid(notation: &str) -> bool {
+        FaceCube::try_from(notation)
+            .ok()
+            .is_some_and(|x| CubieCube::try_from(&x).is_ok())
+    }
+
+    #[test]
+    fn test_diagnose() {
+        assert!(diagnose(SOLVED).is_empty());
+        let mut chars: Vec<char> = SOLVED.chars().collect();
+        // flip the UF edge
+        chars.swap(7, 19);
+        let flipped: String = chars.iter().collect();
+        assert_eq!(diagnose(&flipped), vec![Violation::EdgeFlip { sum: 1 }]);
+        assert!(!is_valid(&flipped));
+        // swap the UF and UR edges
+        let mut chars: Vec<char> = SOLVED.chars().collect();
+        chars.swap(7, 5);
+        chars.swap(19, 10);
+        let swapped: String = chars.iter().collect();
+        assert_eq!(diagnose(&swapped), vec![Violation::Parity]);
+        assert!(!is_valid(&swapped));
+    }
+
+    #[test]
+    fn repairs_colour_count_from_the_colour_appearing_too_often() {
+        let mut cube = Cube::init();
+        cube.import("scan_test_files/solvable.txt".to_string())
+            .expect("Could not load scan file");
+        let notation = cube.to_notation();
+        assert!(is_valid(&notation));
+        // R is listed before B, and has no repair of its own
+        let mut chars = notation.chars().collect::<Vec<_>>();
+        let facelet = (0..54).find(|i| chars[*i] == 'R' && i % 9 != 4).unwrap();
+        chars[facelet] = 'B';
+        let unbalanced: String = chars.iter().collect();
+        assert_eq!(
+            diagnose(&unbalanced)[..2],
+            [
+                Violation::ColourCount {
+                    colour: 'R',
+                    count: 8
+                },
+                Violation::ColourCount {
+                    colour: 'B',
+                    count: 10
+                }
+            ]
+        );
+        let (fixed, _) = targeted_repair(
+            &cube.feature_points(),
+            &unbalanced,
+            &FixerConfig::default(),
+            Instant::now(),
+        );
+        let (_, fixed) = fixed.expect("No repair of the colour counts");
+        assert!(is_valid(&fixed));
+    }
+}
@@ -1,12 +1,132 @@
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::classification::ColorPoint;
+use crate::classification::{colour_centroids, FeaturePoint};
 use crate::constants::CENTRE_INDICES;
+use crate::constants::FACES;
 use crate::constants::SIDE_INDICES;
 use itertools::Itertools;
 use kewb::CubieCube;
 use kewb::FaceCube;
-use paris::log;
+use serde::Serialize;
+
+use crate::assignment_fixer::find_assignment_fix;
+use crate::calibration::ColourModel;
+use crate::diagnosis::targeted_repair;
+use crate::objective::{IncrementalScore, Objective, Scorer};
+use crate::report::FixReport;
+
+/// Technique used to turn a possibly invalid notation into a valid one
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fixer {
+    /// Local search over swaps, relabels and cycles, followed by a search of the closest valid notations
+    #[default]
+    Search,
+    /// Heuristic alternating branch and bound over piece assignments, finding the best valid state for the colour centroids,
+    /// which are then re-estimated from it until it stops changing
+    #[value(alias = "exact")]
+    Assignment,
+    /// Targeted repairs of the diagnosed violations, only touching the implicated facelets
+    Repair,
+}
+
+/// Error returned when a notation could not be fixed
+#[derive(Clone, Debug, PartialEq)]
+pub enum FixError {
+    /// The fixer ended without finding any valid state
+    NoValidState,
+    /// The fixer ran out of time or was cancelled before finding any valid state
+    Stopped,
+}
+
+impl Display for FixError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FixError::NoValidState => write!(f, "No valid cube state was found"),
+            FixError::Stopped => write!(
+                f,
+                "The fixer was stopped before finding any valid cube state"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FixError {}
+
+/// Progress of the search fixer, reported once every depth has been explored
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// Number of swaps combined in the explored candidates
+    pub depth: usize,
+    /// Number of candidates explored at this depth
+    pub candidates: usize,
+    /// Time since the fixer started
+    pub elapsed: Duration,
+    /// Score of the best valid notation found so far, if any
+    pub best_score: Option<f64>,
+}
+
+/// Statistics of a fixer run
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct SearchStats {
+    /// Deepest combination of swaps explored by the search fixer
+    pub depth: Option<usize>,
+    /// Number of notations, or partial piece assignments for the assignment fixer, evaluated
+    pub candidates: usize,
+    /// Time spent fixing
+    pub elapsed: Duration,
+    /// Whether the search was cancelled or ran out of time
+    pub stopped: bool,
+}
+
+/// Callback receiving the progress of the search fixer
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Settings of the fixers.
+/// Every fixer stops early when it runs out of time or is cancelled, keeping the best valid notation found so far
+#[derive(Clone)]
+pub struct FixerConfig {
+    /// Number of threads evaluating candidates
+    pub threads: usize,
+    /// Time after which the search stops and keeps the best valid notation found so far
+    pub time_budget: Option<Duration>,
+    /// Stops the search as soon as it is set, keeping the best valid notation found so far
+    pub cancel: Arc<AtomicBool>,
+    /// Called with the progress of the search
+    pub progress: Option<ProgressCallback>,
+    /// Quantity minimised by the search fixer
+    pub objective: Objective,
+    /// Colour model used by the likelihood objective
+    pub model: Option<ColourModel>,
+}
+
+impl Default for FixerConfig {
+    fn default() -> Self {
+        FixerConfig {
+            threads: thread::available_parallelism().map_or(1, |x| x.get()),
+            time_budget: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+            progress: None,
+            objective: Objective::default(),
+            model: None,
+        }
+    }
+}
+
+impl FixerConfig {
+    /// Returns true if the search started at `start` was cancelled or ran out of time
+    pub fn should_stop(&self, start: Instant) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+            || self
+                .time_budget
+                .is_some_and(|budget| start.elapsed() >= budget)
+    }
+}
 
 /// Calculates the score for a given notation based on the closeness to the mean RGB values of facelets.
 ///
@@ -16,7 +136,7 @@ use paris::log;
 ///
 /// # Returns
 /// The score (f64) for the given notation.
-fn calculate_score(rgb_values: &Vec<ColorPoint>, notation: &str) -> f64 {
+pub fn calculate_score(rgb_values: &[FeaturePoint], notation: &str) -> f64 {
     // get groups of rgb values
     let chars = notation.chars().collect_vec();
     let mut groups = HashMap::new();
@@ -29,13 +149,13 @@ fn calculate_score(rgb_values: &Vec<ColorPoint>, notation: &str) -> f64 {
     for group in groups.values() {
         let mean = group
             .iter()
-            .fold([0., 0., 0.], |acc, x| {
-                [acc[0] + x.r, acc[1] + x.g, acc[2] + x.b]
+            .fold([0., 0., 0., 0.], |acc, x| {
+                [acc[0] + x.r, acc[1] + x.g, acc[2] + x.b, acc[3] + x.l]
             })
             .map(|x| x / group.len() as f64);
         for color in group {
-            let distance = (0..3)
-                .map(|i| (color.to_array()[i] - mean[i]).powi(2))
+            let distance = (0..4)
+                .map(|i| (color.to_features()[i] - mean[i]).powi(2))
                 .sum::<f64>()
                 .sqrt();
             score += distance;
@@ -44,112 +164,334 @@ fn calculate_score(rgb_values: &Vec<ColorPoint>, notation: &str) -> f64 {
     score
 }
 
-/// Generates all possible swap options for the given characters,
-/// excluding the centre facelets and useless swaps of same characters.
+/// Change of the colours of some facelets, explored by the search fixer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Move {
+    /// Exchanges the colours of two facelets
+    Swap(usize, usize),
+    /// Gives another colour to a facelet
+    Relabel(usize, char),
+    /// Gives the colour of the first facelet to the second, the colour of the second to the third and the colour of the third to the first
+    Cycle(usize, usize, usize),
+}
+
+impl Move {
+    /// Distance between the readings of the facelets exchanging their colours,
+    /// or between the reading of the relabelled facelet and the mean of its new colour
+    fn distance(
+        &self,
+        rgb_values: &[FeaturePoint],
+        centroids: &HashMap<char, FeaturePoint>,
+    ) -> f64 {
+        match *self {
+            Move::Swap(i, j) => rgb_values[i].distance_to(&rgb_values[j]),
+            Move::Relabel(i, colour) => centroids
+                .get(&colour)
+                .map_or(f64::INFINITY, |x| rgb_values[i].distance_to(x)),
+            Move::Cycle(i, j, k) => [(i, j), (j, k), (k, i)]
+                .map(|(a, b)| rgb_values[a].distance_to(&rgb_values[b]))
+                .into_iter()
+                .fold(0., f64::max),
+        }
+    }
+}
+
+/// Number of candidates a thread of the search fixer takes from the queue at once
+const CHUNK_SIZE: usize = 256;
+
+/// Returns true if the facelets are of the same kind: centres, edges or corners
+fn same_kind(i: usize, j: usize) -> bool {
+    CENTRE_INDICES.contains(&i) == CENTRE_INDICES.contains(&j)
+        && SIDE_INDICES.contains(&i) == SIDE_INDICES.contains(&j)
+}
+
+/// Generates all possible moves for the given characters:
+/// - swaps, excluding useless swaps of same characters and swaps between different kinds of facelets
+/// - relabels of a facelet from a colour appearing more than 9 times to a colour appearing less than 9 times,
+///   excluding the centre facelets
 ///
 /// # Arguments
-/// * `chars` - The characters to generate swap options for.
+/// * `chars` - The characters to generate moves for.
 ///
 /// # Returns
-/// A vector of vectors, where each inner vector represents a swap option.
-fn generate_swap_options(chars: &Vec<char>) -> Vec<Vec<usize>> {
-    return (0..54)
-        .combinations(2)
-        .filter(|x| &chars[x[0]] != &chars[x[1]])
-        .filter(|x| CENTRE_INDICES.contains(&x[0]) == CENTRE_INDICES.contains(&x[1]))
-        .filter(|x| SIDE_INDICES.contains(&x[0]) == SIDE_INDICES.contains(&x[1]))
+/// A vector of every move.
+fn generate_moves(chars: &[char]) -> Vec<Move> {
+    let mut moves = (0..54)
+        .tuple_combinations()
+        .filter(|(i, j)| chars[*i] != chars[*j] && same_kind(*i, *j))
+        .map(|(i, j)| Move::Swap(i, j))
         .collect_vec();
+    let count = |c: char| chars.iter().filter(|x| **x == c).count();
+    let missing = FACES.into_iter().filter(|c| count(*c) < 9).collect_vec();
+    for i in (0..54).filter(|i| !CENTRE_INDICES.contains(i) && count(chars[*i]) > 9) {
+        moves.extend(missing.iter().map(|c| Move::Relabel(i, *c)));
+    }
+    moves
+}
+
+/// Generates the cycles of three facelets of the same kind, excluding the centre facelets, in both directions.
+/// They are much more numerous than the other moves and do not depend on the notation,
+/// so they are generated once and only filtered with [is_useful_cycle] afterwards.
+fn generate_cycles() -> Vec<Move> {
+    (0..54)
+        .filter(|i| !CENTRE_INDICES.contains(i))
+        .tuple_combinations()
+        .filter(|(i, j, k)| same_kind(*i, *j) && same_kind(*j, *k))
+        .flat_map(|(i, j, k)| [Move::Cycle(i, j, k), Move::Cycle(i, k, j)])
+        .collect()
+}
+
+/// Returns true if the cycle changes the notation more than a swap would, its three facelets having different colours
+fn is_useful_cycle(mv: &Move, chars: &[char]) -> bool {
+    match *mv {
+        Move::Cycle(i, j, k) => {
+            chars[i] != chars[j] && chars[j] != chars[k] && chars[k] != chars[i]
+        }
+        _ => true,
+    }
 }
 
-/// Applies the given swaps to the characters and returns the resulting string.
+/// Finds the valid candidate with the lowest score, evaluating the candidates across threads.
+/// Threads take chunks of candidates from a shared queue, and share the best score found so far, so that a candidate that cannot win is not checked for validity.
+/// Ties are broken by the position of the candidate in the iterator, so the result does not depend on the number of threads.
 ///
 /// # Arguments
-/// * `chars` - The characters to apply the swaps to.
-/// * `swaps` - The swaps to apply.
+/// * `scorer` - The scorer of the notations of the scan.
+/// * `base` - The score of the notation to apply the moves of the candidates to.
+/// * `candidates` - The moves of every candidate.
+/// * `bound` - Score a candidate has to be lower than.
+/// * `config` - The fixer settings.
+/// * `start` - When the fixer started, to enforce the time budget.
 ///
 /// # Returns
-/// The resulting string after applying the swaps.
-fn apply_swaps(chars: &Vec<char>, swaps: &Vec<&Vec<usize>>) -> String {
-    let mut chars = chars.clone();
-    for swap in swaps {
-        let (i, j) = (swap[0], swap[1]);
-        chars.swap(i, j);
-    }
-    chars.iter().collect()
+/// The score of the best valid candidate and its notation, if any beats the bound, along with the number of candidates explored.
+/// If the search is stopped, only the candidates explored until then are considered.
+fn best_valid_candidate<'a, I>(
+    scorer: &Scorer,
+    base: &IncrementalScore,
+    candidates: I,
+    bound: f64,
+    config: &FixerConfig,
+    start: Instant,
+) -> (Option<(f64, String)>, usize)
+where
+    I: Iterator<Item = Vec<&'a Move>> + Send,
+{
+    // scores are never negative, so their bits are ordered like them
+    let shared_bound = AtomicU64::new(bound.to_bits());
+    let explored = AtomicUsize::new(0);
+    let queue = Mutex::new(candidates.enumerate());
+    let threads = config.threads.max(1);
+    let best = thread::scope(|scope| {
+        let handles = (0..threads)
+            .map(|_| {
+                let queue = &queue;
+                let shared_bound = &shared_bound;
+                let explored = &explored;
+                scope.spawn(move || {
+                    let mut best: Option<(f64, usize, String)> = None;
+                    'queue: loop {
+                        let chunk = queue
+                            .lock()
+                            .unwrap()
+                            .by_ref()
+                            .take(CHUNK_SIZE)
+                            .collect_vec();
+                        if chunk.is_empty() {
+                            break;
+                        }
+                        for (i, option) in chunk {
+                            if config.should_stop(start) {
+                                break 'queue;
+                            }
+                            explored.fetch_add(1, Ordering::Relaxed);
+                            // every candidate starts from the same copy, so its score does not depend on the thread
+                            let mut permutted = *base;
+                            for mv in option {
+                                permutted.apply(scorer, mv);
+                            }
+                            let score = permutted.score(scorer);
+                            let bound = f64::from_bits(shared_bound.load(Ordering::Relaxed));
+                            if score > bound {
+                                continue;
+                            }
+                            let permutted_string = permutted.notation();
+                            if let Ok(facecube) = FaceCube::try_from(permutted_string.as_str()) {
+                                if CubieCube::try_from(&facecube).is_ok() {
+                                    let improves = match &best {
+                                        Some(b) => score < b.0,
+                                        None => true,
+                                    };
+                                    if improves {
+                                        best = Some((score, i, permutted_string));
+                                    }
+                                    shared_bound.fetch_min(score.to_bits(), Ordering::Relaxed);
+                                }
+                            }
+                        }
+                    }
+                    best
+                })
+            })
+            .collect_vec();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().unwrap())
+            .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+            .filter(|best| best.0 < bound)
+            .map(|(score, _, notation)| (score, notation))
+    });
+    (best, explored.into_inner())
 }
 
 /// Finds the optimal valid notation for the given (possibly invalid) notation.
+/// The search stops early as described in [FixerConfig].
+/// Candidates are compared on the objective of the settings, and only the returned notation gets its [calculate_score],
+/// the score every fixer reports.
 ///
 /// # Arguments
 /// * `rgb_values` - The previously scanned facelet RGB tuples.
 /// * `nota` - The initial notation string to be fixed.
+/// * `config` - The fixer settings.
 ///
 /// # Returns
-/// A tuple containing the best score (f64) and its corresponding notation (String).
-pub fn find_optimal_fix(rgb_values: &Vec<ColorPoint>, nota: String) -> (f64, String) {
-    let mut chars = nota.chars().collect_vec();
-    let swap_options = generate_swap_options(&chars);
+/// A tuple containing the best score (f64) and its corresponding notation (String),
+/// or an error if no valid notation was found, along with the statistics of the search.
+pub fn find_optimal_fix(
+    rgb_values: &[FeaturePoint],
+    nota: String,
+    config: &FixerConfig,
+) -> (Result<(f64, String), FixError>, SearchStats) {
+    let start = Instant::now();
+    let mut stats = SearchStats::default();
+    let chars = nota.chars().collect_vec();
     // find local optimum
-    let mut best_score: (f64, String) = (f64::INFINITY, nota.clone());
-    let mut continue_search = true;
-    while continue_search {
-        let mut best_local_score: (f64, String) = (f64::INFINITY, nota.clone());
-        for swap in swap_options.clone() {
-            let permutted_string = apply_swaps(&chars, &vec![&swap]);
-            let score = calculate_score(rgb_values, &permutted_string);
-            if score < best_local_score.0 {
-                best_local_score = (score, permutted_string.clone());
-            }
+    let scorer = Scorer::init(rgb_values, config.objective, config.model.as_ref());
+    let mut local = IncrementalScore::init(&scorer, &chars);
+    let cycles = generate_cycles();
+    loop {
+        if config.should_stop(start) {
+            stats.stopped = true;
+            break;
         }
-        if best_local_score.0 < best_score.0 {
-            best_score = best_local_score;
-            chars = best_score.1.chars().collect_vec();
-        } else {
-            continue_search = false;
+        let chars = local.notation().chars().collect_vec();
+        let mut moves = generate_moves(&chars);
+        moves.extend(cycles.iter().filter(|x| is_useful_cycle(x, &chars)));
+        let best_local_move = moves
+            .iter()
+            .map(|mv| (local.score_after(&scorer, mv), mv))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        stats.candidates += moves.len();
+        match best_local_move {
+            Some((score, mv)) if score < local.score(&scorer) => local.apply(&scorer, mv),
+            _ => break,
         }
     }
-    // find closest fix to the local minimum
-    let mut best_score: (f64, String) = (f64::INFINITY, chars.iter().collect());
+    let chars = local.notation().chars().collect_vec();
+    let centroids = colour_centroids(rgb_values, &chars);
+    // find closest fix to the local minimum, starting from the quick targeted repair
+    let (repaired, evaluations) = targeted_repair(rgb_values, &local.notation(), config, start);
+    stats.candidates += evaluations;
+    let mut best_score: (f64, String) = match repaired {
+        Some((_, notation)) => (scorer.score(&notation), notation),
+        None => (f64::INFINITY, local.notation()),
+    };
 
     for k in 0..100_usize {
-        log!("Exploring permutations at depth {k}");
-        let mut swap_options = generate_swap_options(&chars);
-        if (swap_options.len() as i32 / (2_i32).pow(k as u32) - 1) < 0 {
+        if config.should_stop(start) {
+            stats.stopped = true;
             break;
         }
-        let epsilon: i32 = swap_options
+        let moves = generate_moves(&chars);
+        if (moves.len() as i32 / (2_i32).pow(k as u32) - 1) < 0 {
+            break;
+        }
+        let distances = moves
             .iter()
-            .map(|x| (rgb_values[x[0]].distance_to(&rgb_values[x[1]]) * 1000.) as i32)
+            .map(|x| x.distance(rgb_values, &centroids))
+            .collect_vec();
+        let epsilon: i32 = distances
+            .iter()
+            .map(|x| (x * 1000.) as i32)
             .sorted()
-            .nth((swap_options.len() / (2_i32).pow(k as u32) as usize) - 1)
+            .nth((moves.len() / (2_i32).pow(k as u32) as usize) - 1)
             .unwrap();
-        println!("Epsilon is: {}", epsilon);
-        swap_options = swap_options
+        let moves = moves
             .iter()
-            .filter(|x| rgb_values[x[0]].distance_to(&rgb_values[x[1]]) * 1000. < epsilon as f64)
-            .map(|x| x.clone())
+            .zip(&distances)
+            .filter(|(_, distance)| *distance * 1000. < epsilon as f64)
+            .map(|(x, _)| *x)
             .collect_vec();
-        let to_be_tried = swap_options.iter().combinations(k);
-        for option in to_be_tried {
-            let permutted_string = apply_swaps(&chars, &option);
-            if let Ok(facecube) = FaceCube::try_from(permutted_string.as_str()) {
-                if CubieCube::try_from(&facecube).is_ok() {
-                    let score = calculate_score(rgb_values, &permutted_string);
-                    if score < best_score.0 {
-                        best_score = (score, permutted_string);
-                    }
-                }
-            }
+        let to_be_tried = moves.iter().combinations(k);
+        let (best, candidates) =
+            best_valid_candidate(&scorer, &local, to_be_tried, best_score.0, config, start);
+        if let Some(best) = best {
+            best_score = best;
         }
+        stats.depth = Some(k);
+        stats.candidates += candidates;
+        if let Some(progress) = &config.progress {
+            progress(&Progress {
+                depth: k,
+                candidates,
+                elapsed: start.elapsed(),
+                best_score: Some(best_score.0).filter(|x| x.is_finite()),
+            });
+        }
+    }
+    stats.elapsed = start.elapsed();
+    if best_score.0.is_finite() {
+        let score = calculate_score(rgb_values, &best_score.1);
+        (Ok((score, best_score.1)), stats)
+    } else if stats.stopped {
+        (Err(FixError::Stopped), stats)
+    } else {
+        (Err(FixError::NoValidState), stats)
     }
-    best_score
+}
+
+/// Finds a valid notation for the given (possibly invalid) notation using the given fixer.
+/// Returns a report of what the fixer changed, or an error if no valid notation was found.
+pub fn fix_with(
+    fixer: Fixer,
+    rgb_values: &[FeaturePoint],
+    nota: String,
+    config: &FixerConfig,
+) -> Result<FixReport, FixError> {
+    let start = Instant::now();
+    let (fixed, mut stats) = match fixer {
+        Fixer::Search => find_optimal_fix(rgb_values, nota.clone(), config),
+        Fixer::Assignment => find_assignment_fix(rgb_values, nota.clone(), config),
+        Fixer::Repair => {
+            let (fixed, candidates) = targeted_repair(rgb_values, &nota, config, start);
+            let stats = SearchStats {
+                candidates,
+                stopped: fixed.is_none() && config.should_stop(start),
+                ..Default::default()
+            };
+            let error = if stats.stopped {
+                FixError::Stopped
+            } else {
+                FixError::NoValidState
+            };
+            (fixed.ok_or(error), stats)
+        }
+    };
+    stats.elapsed = start.elapsed();
+    Ok(FixReport::init(rgb_values, nota, fixed?, stats))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs::read_dir;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use clap::ValueEnum;
+
     use crate::cube::Cube;
+    use crate::diagnosis::diagnose;
     use crate::fixer;
-    use std::fs::read_dir;
 
     #[test]
     fn official_solved_cube_test() {
@@ -166,7 +508,13 @@ mod tests {
             tested += 1;
             correct += 1;
             let cube_notation = cube.to_notation();
-            let fixed = fixer::find_optimal_fix(&cube.facelet_rgb_values, cube_notation.clone());
+            let fixed = fixer::find_optimal_fix(
+                &cube.feature_points(),
+                cube_notation.clone(),
+                &fixer::FixerConfig::default(),
+            )
+            .0
+            .expect("No valid state found");
             println!("Cube notation is: {}", fixed.1);
             let mut consecutive = 0;
             let mut current = 'U';
@@ -185,4 +533,70 @@ mod tests {
         println!("correct : {correct} / {tested}");
         assert_eq!(correct, tested);
     }
+
+    #[test]
+    fn unbalanced_notation_is_relabelled() {
+        let mut cube = Cube::init();
+        cube.import("scan_test_files/official_cube_solved/2024-06-05_23-36-17".to_string())
+            .expect("Could not load scan file");
+        let truth = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        let mut chars = truth.chars().collect::<Vec<_>>();
+        // ten B and eight R: no swap can fix it
+        chars[10] = 'B';
+        let relabels = fixer::generate_moves(&chars)
+            .into_iter()
+            .filter(|x| matches!(x, fixer::Move::Relabel(..)))
+            .collect::<Vec<_>>();
+        assert_eq!(relabels.len(), 9);
+        assert!(relabels.contains(&fixer::Move::Relabel(10, 'R')));
+        let (fixed, _) = fixer::find_optimal_fix(
+            &cube.feature_points(),
+            chars.iter().collect(),
+            &fixer::FixerConfig::default(),
+        );
+        assert_eq!(fixed.unwrap().1, truth);
+    }
+
+    #[test]
+    fn thread_count_does_not_change_fix() {
+        let mut cube = Cube::init();
+        cube.import("scan_test_files/hard_to_solve.txt".to_string())
+            .expect("Could not load scan file");
+        let fix = |threads| {
+            let config = fixer::FixerConfig {
+                threads,
+                ..Default::default()
+            };
+            fixer::find_optimal_fix(&cube.feature_points(), cube.to_notation(), &config)
+                .0
+                .unwrap()
+                .1
+        };
+        assert_eq!(fix(1), fix(4));
+    }
+
+    #[test]
+    fn cancelled_search_is_valid() {
+        let mut cube = Cube::init();
+        cube.import("scan_test_files/hard_to_solve.txt".to_string())
+            .expect("Could not load scan file");
+        let points = cube.feature_points();
+        // cancelled once the first depth is explored, so the notation found by then is kept
+        let mut config = fixer::FixerConfig::default();
+        let cancel = config.cancel.clone();
+        config.progress = Some(Arc::new(move |_| cancel.store(true, Ordering::Relaxed)));
+        let (fixed, stats) = fixer::find_optimal_fix(&points, cube.to_notation(), &config);
+        assert!(stats.stopped);
+        assert_eq!(stats.depth, Some(0));
+        let (_, fixed) = fixed.unwrap();
+        assert!(diagnose(&fixed).is_empty(), "{fixed} is invalid");
+
+        // cancelled before starting, no fixer can find anything
+        let config = fixer::FixerConfig::default();
+        config.cancel.store(true, Ordering::Relaxed);
+        for fixer in fixer::Fixer::value_variants() {
+            let fixed = fixer::fix_with(*fixer, &points, cube.to_notation(), &config);
+            assert_eq!(fixed.unwrap_err(), fixer::FixError::Stopped, "{fixer:?}");
+        }
+    }
 }
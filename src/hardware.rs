@@ -1,3 +1,5 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
 use std::thread::sleep;
 use std::time::Duration;
 
@@ -5,11 +7,150 @@ use colored::Colorize;
 use ev3dev_lang_rust::motors::{MotorPort, TachoMotor};
 use ev3dev_lang_rust::sensors::ColorSensor;
 use ev3dev_lang_rust::Ev3Result;
+use itertools::Itertools;
+use kewb::Solution;
 use paris::{info, log, success};
 
-use crate::classification::ColorPoint;
+use crate::aggregation::Aggregation;
 use crate::constants::SCAN_ORDER;
 use crate::cube::Cube;
+use crate::journal::{Event, Journal};
+
+/// Position of the sensor arm (in degrees from its reset position) and of the base (in eighths of a turn)
+/// when scanning every facelet of a face, in the order of [SCAN_ORDER]
+const SCAN_PATH: [(i32, usize); 9] = [
+    (-670, 0),
+    (-570, 0),
+    (-550, 1),
+    (-610, 2),
+    (-570, 3),
+    (-605, 4),
+    (-565, 5),
+    (-595, 6),
+    (-555, 7),
+];
+
+/// Orientation of the cube as it is placed in the robot before a scan, as stored in [Cube].
+/// The first flip of [Hardware::scan_cube] turns it into the first of [SCAN_ORIENTATIONS]
+pub const START_ORIENTATION: ([char; 4], char, char) = (['F', 'D', 'B', 'U'], 'R', 'L');
+
+/// Faces that can be accessed by flipping (the first one being down), right face and left face,
+/// as stored in [Cube], when every face is scanned. Faces are listed in the order they are scanned
+const SCAN_ORIENTATIONS: [([char; 4], char, char); 6] = [
+    (['D', 'B', 'U', 'F'], 'R', 'L'),
+    (['B', 'U', 'F', 'D'], 'R', 'L'),
+    (['U', 'F', 'D', 'B'], 'R', 'L'),
+    (['F', 'D', 'B', 'U'], 'R', 'L'),
+    (['L', 'U', 'R', 'D'], 'B', 'F'),
+    (['R', 'D', 'L', 'U'], 'B', 'F'),
+];
+
+/// Orientation of the cube once flipped
+fn flipped((mut next_faces, right, left): ([char; 4], char, char)) -> ([char; 4], char, char) {
+    next_faces.rotate_left(1);
+    (next_faces, right, left)
+}
+
+/// Orientation of the cube once the base is rotated by 90 degrees, clockwise or not
+fn rotated(
+    (next_faces, right, left): ([char; 4], char, char),
+    clockwise: bool,
+) -> ([char; 4], char, char) {
+    let [down, front, up, back] = next_faces;
+    if clockwise {
+        ([down, left, up, right], front, back)
+    } else {
+        ([down, right, up, left], back, front)
+    }
+}
+
+/// Move changing the orientation of the cube
+#[derive(Clone, Copy)]
+enum Reorientation {
+    Flip,
+    /// Rotation of the base by 90 degrees, clockwise or not
+    Rotate(bool),
+}
+
+/// Movement of the robot, as executed when applying a solution
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Primitive {
+    /// Flips the cube, bringing the back face down
+    Flip,
+    /// Holds the upper layers of the cube with the flipper arm
+    Lock,
+    /// Releases the cube
+    Unlock,
+    /// Rotation of the whole cube by 90 degrees, clockwise or not
+    BaseTurn { clockwise: bool },
+    /// Turn of the down face by the given number of quarter turns: 1, -1 for counterclockwise, or 2
+    FaceTurn { quarters: i32 },
+}
+
+impl Display for Primitive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Primitive::Flip => write!(f, "flip"),
+            Primitive::Lock => write!(f, "lock"),
+            Primitive::Unlock => write!(f, "unlock"),
+            Primitive::BaseTurn { clockwise: true } => write!(f, "base_turn_90"),
+            Primitive::BaseTurn { clockwise: false } => write!(f, "base_turn_-90"),
+            Primitive::FaceTurn { quarters } => write!(f, "face_turn_{}", 90 * quarters),
+        }
+    }
+}
+
+/// Plans the primitives applying a move of the solution, such as `R'`,
+/// from the given orientation of the cube and state of the flipper, which are updated
+pub fn plan_solution_part(
+    part: &str,
+    orientation: &mut ([char; 4], char, char),
+    locked: &mut bool,
+) -> Vec<Primitive> {
+    let mut primitives = vec![];
+    let face = part.chars().next().unwrap();
+    if !orientation.0.contains(&face) {
+        // then we have to rotate
+        if *locked {
+            primitives.push(Primitive::Unlock);
+            *locked = false;
+        }
+        let clockwise = face == orientation.2;
+        primitives.push(Primitive::BaseTurn { clockwise });
+        *orientation = rotated(*orientation, clockwise);
+    }
+    // the flipper locks the cube before flipping it
+    if !*locked {
+        primitives.push(Primitive::Lock);
+        *locked = true;
+    }
+    while orientation.0[0] != face {
+        primitives.push(Primitive::Flip);
+        *orientation = flipped(*orientation);
+    }
+    let quarters = if part.len() == 1 {
+        1
+    } else if part.ends_with('\'') {
+        -1
+    } else {
+        2
+    };
+    primitives.push(Primitive::FaceTurn { quarters });
+    primitives
+}
+
+/// Plans the primitives applying the whole solution, from the given orientation of the cube and state of the flipper
+pub fn plan_solution(
+    solution: &Solution,
+    mut orientation: ([char; 4], char, char),
+    mut locked: bool,
+) -> Vec<Primitive> {
+    solution
+        .get_all_moves()
+        .iter()
+        .flat_map(|x| plan_solution_part(&x.to_string(), &mut orientation, &mut locked))
+        .collect()
+}
 
 /// A representation of the robot hardware, as in motors and sensor.
 pub struct Hardware {
@@ -29,10 +170,19 @@ pub struct Hardware {
     pub movement: i32,
     /// Number of scans for a single facelet
     pub iterations: usize,
+    /// Method used to reduce the scans of a facelet into a single reading
+    pub aggregation: Aggregation,
+    /// Journal the sensor reads and the primitives are recorded to
+    pub journal: Journal,
 }
 
 impl Hardware {
-    pub fn init(sleep_duration: Duration, movement: i32, iterations: usize) -> Ev3Result<Self> {
+    pub fn init(
+        sleep_duration: Duration,
+        movement: i32,
+        iterations: usize,
+        aggregation: Aggregation,
+    ) -> Ev3Result<Self> {
         let base_motor: TachoMotor = TachoMotor::get(MotorPort::OutC)?;
         base_motor.set_speed_sp(base_motor.get_max_speed()?)?;
         let flipper_motor: TachoMotor = TachoMotor::get(MotorPort::OutD)?;
@@ -47,7 +197,7 @@ impl Hardware {
         }
         let color_sensor = ColorSensor::find()?;
         color_sensor.set_mode_rgb_raw()?;
-        return Ok(Hardware {
+        Ok(Hardware {
             base_motor,
             flipper_motor,
             sensor_motor,
@@ -56,7 +206,9 @@ impl Hardware {
             sleep_duration,
             movement,
             iterations,
-        });
+            aggregation,
+            journal: Journal::disabled(),
+        })
     }
 
     pub fn shutdown() -> Ev3Result<()> {
@@ -88,17 +240,25 @@ impl Hardware {
     }
 
     pub fn rot_base45(&self) -> Ev3Result<()> {
-        Self::run_for_rot(&self.base_motor, 0.375)?;
+        self.journal.primitive("base_turn_45", || {
+            Self::run_for_rot(&self.base_motor, 0.375)
+        })?;
         Ok(())
     }
 
     pub fn rot_base90(&self) -> Ev3Result<()> {
-        Self::run_for_rot(&self.base_motor, 0.75)?;
+        self.journal
+            .primitive(&Primitive::BaseTurn { clockwise: true }.to_string(), || {
+                Self::run_for_rot(&self.base_motor, 0.75)
+            })?;
         Ok(())
     }
 
     pub fn rot_base90cc(&self) -> Ev3Result<()> {
-        Self::run_for_rot(&self.base_motor, -0.75)?;
+        self.journal.primitive(
+            &Primitive::BaseTurn { clockwise: false }.to_string(),
+            || Self::run_for_rot(&self.base_motor, -0.75),
+        )?;
         Ok(())
     }
 
@@ -106,39 +266,53 @@ impl Hardware {
         if !self.locked {
             self.lock_cube()?;
         }
-        Self::run_for_deg(&self.flipper_motor, 90)?;
-        sleep(Duration::from_millis(100));
-        Self::run_for_deg(&self.flipper_motor, -90)?;
-        sleep(Duration::from_millis(100));
-        Ok(())
+        self.journal
+            .primitive(&Primitive::Flip.to_string(), || -> Ev3Result<()> {
+                Self::run_for_deg(&self.flipper_motor, 90)?;
+                sleep(Duration::from_millis(100));
+                Self::run_for_deg(&self.flipper_motor, -90)?;
+                sleep(Duration::from_millis(100));
+                Ok(())
+            })
     }
 
     pub fn lock_cube(&mut self) -> Ev3Result<()> {
-        Self::run_for_deg(&self.flipper_motor, 100)?;
+        self.journal.primitive(&Primitive::Lock.to_string(), || {
+            Self::run_for_deg(&self.flipper_motor, 100)
+        })?;
         self.locked = true;
         Ok(())
     }
 
     pub fn unlock_cube(&mut self) -> Ev3Result<()> {
-        Self::run_for_deg(&self.flipper_motor, -100)?;
+        self.journal.primitive(&Primitive::Unlock.to_string(), || {
+            Self::run_for_deg(&self.flipper_motor, -100)
+        })?;
         self.locked = false;
         Ok(())
     }
 
     pub fn reset_sensor_position(&self) -> Ev3Result<()> {
-        self.sensor_motor.run_forever()?;
-        self.sensor_motor
-            .wait_until(TachoMotor::STATE_STALLED, None);
-        Self::run_for_deg(&self.sensor_motor, -10)?;
-        self.sensor_motor.stop()?;
-        Ok(())
+        self.journal.primitive("sensor_reset", || -> Ev3Result<()> {
+            self.sensor_motor.run_forever()?;
+            self.sensor_motor
+                .wait_until(TachoMotor::STATE_STALLED, None);
+            Self::run_for_deg(&self.sensor_motor, -10)?;
+            self.sensor_motor.stop()?;
+            Ok(())
+        })
     }
 
     pub fn sensor_scan(&self, data: &mut Cube) -> Ev3Result<()> {
+        let idx = SCAN_ORDER[data.curr_idx];
         let mut scans = vec![[0.; 3]; self.iterations];
-        for i in 0..self.iterations {
+        for sample in scans.iter_mut() {
             let scan = self.color_sensor.get_rgb()?;
-            scans[i] = [scan.0 as f64, scan.1 as f64, scan.2 as f64];
+            *sample = [scan.0 as f64, scan.1 as f64, scan.2 as f64];
+            self.journal.record(Event::SensorRead {
+                facelet: idx,
+                sample: *sample,
+            });
             Hardware::run_for_deg(&self.sensor_motor, self.movement)?;
             sleep(self.sleep_duration);
         }
@@ -146,13 +320,11 @@ impl Hardware {
             &self.sensor_motor,
             (-self.movement) * self.iterations as i32,
         )?;
-        let mut rgb = [
-            scans.iter().map(|x| x[0]).sum::<f64>() / self.iterations as f64,
-            scans.iter().map(|x| x[1]).sum::<f64>() / self.iterations as f64,
-            scans.iter().map(|x| x[2]).sum::<f64>() / self.iterations as f64,
-        ];
+        data.raw_samples[idx] = scans;
+        data.aggregate_facelet(idx, self.aggregation);
+        let mut rgb = data.facelet_rgb_values[idx].to_array();
         let norm = rgb.iter().map(|x| x.powi(2)).sum::<f64>().sqrt();
-        rgb = [rgb[0] / norm, rgb[1] / norm, rgb[2] / norm];
+        rgb = rgb.map(|x| x / norm);
         log!(
             "Scanned {}",
             format!("{:?}", rgb.map(|x| { (x * 255.) as u8 })).truecolor(
@@ -161,13 +333,6 @@ impl Hardware {
                 (rgb[2] * 255.) as u8
             )
         );
-        let idx = SCAN_ORDER[data.curr_idx];
-        data.facelet_rgb_values[idx] = ColorPoint {
-            r: rgb[0],
-            g: rgb[1],
-            b: rgb[2],
-            index: idx,
-        };
         data.curr_idx += 1;
         Ok(())
     }
@@ -175,76 +340,63 @@ impl Hardware {
     /// Will apply a transformation. Examples of transformation notations are `R, U, R', U2`
     pub fn apply_solution_part(&mut self, part: String, cube: &mut Cube) -> Ev3Result<()> {
         info!("Applying part {}", part);
-        let face = part.chars().nth(0).unwrap();
-        if !cube.next_faces.contains(&face) {
-            // then we have to rotate
-            if self.locked {
-                self.unlock_cube()?;
-            }
-            if face == cube.left_face {
-                self.rot_base90()?;
-                let tmp = cube.left_face;
-                let tmp2 = cube.right_face;
-                cube.left_face = cube.next_faces[3];
-                cube.right_face = cube.next_faces[1];
-                cube.next_faces[1] = tmp;
-                cube.next_faces[3] = tmp2;
-            } else {
-                self.rot_base90cc()?;
-                let tmp = cube.right_face;
-                let tmp2 = cube.left_face;
-                cube.right_face = cube.next_faces[3];
-                cube.left_face = cube.next_faces[1];
-                cube.next_faces[1] = tmp;
-                cube.next_faces[3] = tmp2;
-            }
-        }
-        while cube.next_faces[0] != face {
-            self.flip_cube()?;
-            cube.next_faces.rotate_left(1);
-        }
-        if !self.locked {
-            self.lock_cube()?;
+        let mut orientation = Self::orientation(cube);
+        let mut locked = self.locked;
+        for primitive in plan_solution_part(&part, &mut orientation, &mut locked) {
+            self.execute(primitive)?;
         }
-        if part.len() == 1 {
-            // 90deg clockwise
+        Self::set_orientation(cube, orientation);
+        Ok(())
+    }
+
+    /// Executes a primitive planned by [plan_solution_part]
+    pub fn execute(&mut self, primitive: Primitive) -> Ev3Result<()> {
+        let (forward, back) = match primitive {
+            Primitive::Flip => return self.flip_cube(),
+            Primitive::Lock => return self.lock_cube(),
+            Primitive::Unlock => return self.unlock_cube(),
+            Primitive::BaseTurn { clockwise: true } => return self.rot_base90(),
+            Primitive::BaseTurn { clockwise: false } => return self.rot_base90cc(),
             // We need to go a little further each time as the base borders are not the same width as the cube
-            Hardware::run_for_rot(&self.base_motor, -0.925)?;
-            Hardware::run_for_rot(&self.base_motor, 0.175)?;
-        } else if part.ends_with('\'') {
-            // 90 deg counterclockwise
-            Hardware::run_for_rot(&self.base_motor, 0.875)?;
-            Hardware::run_for_rot(&self.base_motor, -0.125)?;
-        } else {
-            // 180deg
-            Hardware::run_for_rot(&self.base_motor, 1.650)?;
-            Hardware::run_for_rot(&self.base_motor, -0.150)?;
-        }
-        return Ok(());
+            Primitive::FaceTurn { quarters: 1 } => (-0.925, 0.175),
+            Primitive::FaceTurn { quarters: -1 } => (0.875, -0.125),
+            Primitive::FaceTurn { .. } => (1.650, -0.150),
+        };
+        self.journal
+            .primitive(&primitive.to_string(), || -> Ev3Result<()> {
+                Hardware::run_for_rot(&self.base_motor, forward)?;
+                Hardware::run_for_rot(&self.base_motor, back)
+            })
     }
 
     /// Scans the face facing up and adds the colours to the cube struct
     pub fn scan_face(&mut self, cube: &mut Cube) -> Ev3Result<()> {
+        let start = cube.curr_idx;
+        self.scan_positions(cube, &(start..start + 9).collect::<Vec<_>>())
+    }
+
+    /// Scans the facelets of the face facing up at the given positions of [SCAN_ORDER], following [SCAN_PATH].
+    /// Positions must be sorted and belong to the same face.
+    fn scan_positions(&mut self, cube: &mut Cube, positions: &[usize]) -> Ev3Result<()> {
         self.sensor_motor
             .set_stop_action(TachoMotor::STOP_ACTION_HOLD)?;
         if self.locked {
             self.unlock_cube()?;
         }
-        Hardware::run_for_deg(&self.sensor_motor, -670)?;
-        self.sensor_scan(cube)?;
-        let offsets = [100, -20, 5, 10];
-        for i in 0..4 {
-            Hardware::run_for_deg(&self.sensor_motor, offsets[i])?;
-            self.sensor_scan(cube)?;
-            self.rot_base45()?;
-            if i == 0 {
-                Hardware::run_for_deg(&self.sensor_motor, 20)?;
-            } else {
-                Hardware::run_for_deg(&self.sensor_motor, 40)?;
+        let (mut arm, mut steps) = (0, 0);
+        for position in positions {
+            let (target_arm, target_steps) = SCAN_PATH[position % 9];
+            for _ in steps..target_steps {
+                self.rot_base45()?;
             }
+            Hardware::run_for_deg(&self.sensor_motor, target_arm - arm)?;
+            (arm, steps) = (target_arm, target_steps);
+            cube.curr_idx = *position;
             self.sensor_scan(cube)?;
+        }
+        // a whole turn brings the cube back to its orientation
+        for _ in steps..8 {
             self.rot_base45()?;
-            Hardware::run_for_deg(&self.sensor_motor, -40)?;
         }
         self.reset_sensor_position()?;
         self.sensor_motor
@@ -252,6 +404,88 @@ impl Hardware {
         Ok(())
     }
 
+    /// Faces that can be accessed by flipping, right face and left face of the cube
+    pub fn orientation(cube: &Cube) -> ([char; 4], char, char) {
+        (cube.next_faces, cube.right_face, cube.left_face)
+    }
+
+    pub fn set_orientation(cube: &mut Cube, (next_faces, right, left): ([char; 4], char, char)) {
+        cube.next_faces = next_faces;
+        cube.right_face = right;
+        cube.left_face = left;
+    }
+
+    /// Flips and rotates the cube into the given orientation, with as few moves as possible
+    fn orient(&mut self, cube: &mut Cube, target: ([char; 4], char, char)) -> Ev3Result<()> {
+        // breadth-first search over the 24 orientations
+        let start = Self::orientation(cube);
+        let mut paths = HashMap::from([(start, vec![])]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(orientation) = queue.pop_front() {
+            if orientation == target {
+                break;
+            }
+            for reorientation in [
+                Reorientation::Flip,
+                Reorientation::Rotate(true),
+                Reorientation::Rotate(false),
+            ] {
+                let next = match reorientation {
+                    Reorientation::Flip => flipped(orientation),
+                    Reorientation::Rotate(clockwise) => rotated(orientation, clockwise),
+                };
+                if !paths.contains_key(&next) {
+                    let mut path = paths[&orientation].clone();
+                    path.push(reorientation);
+                    paths.insert(next, path);
+                    queue.push_back(next);
+                }
+            }
+        }
+        for reorientation in paths[&target].clone() {
+            match reorientation {
+                Reorientation::Flip => {
+                    self.flip_cube()?;
+                    sleep(Duration::from_millis(100)); // waiting for the cube to fall
+                    Self::set_orientation(cube, flipped(Self::orientation(cube)));
+                }
+                Reorientation::Rotate(clockwise) => {
+                    if self.locked {
+                        self.unlock_cube()?;
+                    }
+                    if clockwise {
+                        self.rot_base90()?;
+                    } else {
+                        self.rot_base90cc()?;
+                    }
+                    Self::set_orientation(cube, rotated(Self::orientation(cube), clockwise));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Scans the given facelets again, replacing their previous samples.
+    /// Every face is brought back up in the orientation it was scanned in, and the sensor arm only visits the given facelets.
+    pub fn rescan(&mut self, cube: &mut Cube, facelets: &[usize]) -> Ev3Result<()> {
+        let faces = facelets
+            .iter()
+            .map(|idx| SCAN_ORDER.iter().position(|x| x == idx).unwrap())
+            .sorted()
+            .into_group_map_by(|position| position / 9);
+        for (face, positions) in faces.into_iter().sorted() {
+            let orientation = SCAN_ORIENTATIONS[face];
+            self.orient(cube, orientation)?;
+            info!(
+                "Rescanning {} facelets of the {} face...",
+                positions.len(),
+                orientation.0[2]
+            );
+            self.scan_positions(cube, &positions)?;
+        }
+        Ok(())
+    }
+
     pub fn scan_cube(&mut self, cube: &mut Cube) -> Ev3Result<()> {
         for c in ['U', 'F', 'D', 'B'] {
             // U,F,D,B scan
@@ -278,3 +512,46 @@ impl Hardware {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cube::Cube;
+    use crate::hardware::{
+        flipped, plan_solution_part, rotated, Primitive, SCAN_ORIENTATIONS, START_ORIENTATION,
+    };
+
+    #[test]
+    fn scan_orientations_follow_scan_cube() {
+        // replays the moves of scan_cube, from the cube as it is placed in the robot
+        let mut orientations = vec![flipped(START_ORIENTATION)];
+        for _ in 0..3 {
+            orientations.push(flipped(*orientations.last().unwrap()));
+        }
+        let r = flipped(rotated(flipped(orientations[3]), true));
+        orientations.push(r);
+        orientations.push(flipped(flipped(r)));
+        assert_eq!(orientations, SCAN_ORIENTATIONS);
+        let faces: String = orientations.iter().map(|x| x.0[2]).collect();
+        assert_eq!(faces, "UFDBRL");
+        let cube = Cube::init();
+        assert_eq!(
+            SCAN_ORIENTATIONS[5],
+            (cube.next_faces, cube.right_face, cube.left_face)
+        );
+    }
+
+    #[test]
+    fn given_states_start_from_the_placed_cube() {
+        // the F face is down when the cube is placed, so turning it needs no flip
+        let mut orientation = START_ORIENTATION;
+        let mut locked = false;
+        assert_eq!(
+            plan_solution_part("F", &mut orientation, &mut locked),
+            [Primitive::Lock, Primitive::FaceTurn { quarters: 1 }]
+        );
+        assert_eq!(
+            plan_solution_part("R", &mut orientation, &mut locked)[..2],
+            [Primitive::Unlock, Primitive::BaseTurn { clockwise: false }]
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use paris::warn;
+use serde::Serialize;
+
+use crate::aggregation::Aggregation;
+use crate::classification::Classifier;
+use crate::fixer::Fixer;
+use crate::objective::Objective;
+use crate::report::FixReport;
+
+/// Stage of a run recorded in the journal
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Start of the run, with the arguments it was given
+    Start {
+        software_version: String,
+        date: String,
+        arguments: Vec<String>,
+    },
+    /// Settings the run resolved its arguments to, defaults included
+    Config {
+        classifier: Classifier,
+        fixer: Fixer,
+        objective: Objective,
+        threads: usize,
+        time_budget_s: Option<f64>,
+        /// Aggregation recomputing the readings, if any. Scans by the robot use the default one otherwise
+        aggregation: Option<Aggregation>,
+        luminance_weight: f64,
+        unreliable_centres: Vec<char>,
+        /// Calibration profile file, if any
+        calibration: Option<String>,
+        rescan: usize,
+    },
+    /// Raw sample of a facelet read by the color sensor
+    SensorRead { facelet: usize, sample: [f64; 3] },
+    /// Notation given by the classifier
+    Classification { notation: String },
+    /// Report of the fixer
+    Fix { report: FixReport },
+    /// Solution applied to the cube
+    Solution { moves: String, length: usize },
+    /// Robot primitive, once it is executed
+    Primitive { name: String, duration_ms: f64 },
+    /// End of the run
+    End { success: bool, outcome: String },
+}
+
+/// Line of the journal
+#[derive(Serialize)]
+struct Entry<'a> {
+    /// Time since the start of the run (in ms)
+    elapsed_ms: f64,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Records every stage of a run as a line of JSON, so that the run can be analysed afterwards.
+/// Clones write to the same file. A disabled journal records nothing.
+#[derive(Clone)]
+pub struct Journal {
+    file: Option<Arc<Mutex<File>>>,
+    start: Instant,
+}
+
+impl Journal {
+    /// Journal recording nothing
+    pub fn disabled() -> Self {
+        Journal {
+            file: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Creates the journal file, replacing any previous one
+    pub fn create(file_path: &str) -> std::io::Result<Self> {
+        Ok(Journal {
+            file: Some(Arc::new(Mutex::new(File::create(file_path)?))),
+            start: Instant::now(),
+        })
+    }
+
+    /// Appends the event to the journal. Write errors are reported without stopping the run
+    pub fn record(&self, event: Event) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let entry = Entry {
+            elapsed_ms: self.start.elapsed().as_secs_f64() * 1000.,
+            event: &event,
+        };
+        let line = serde_json::to_string(&entry).expect("Could not serialise journal entry");
+        let mut file = file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{line}").and_then(|_| file.flush()) {
+            warn!("Could not write to the journal: {e}");
+        }
+    }
+
+    /// Runs a robot primitive and records how long it took, whether it succeeded or not
+    pub fn primitive<T>(&self, name: &str, primitive: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = primitive();
+        self.record(Event::Primitive {
+            name: name.to_string(),
+            duration_ms: start.elapsed().as_secs_f64() * 1000.,
+        });
+        result
+    }
+
+    /// Records the start of the run with the arguments of the program
+    pub fn record_start(&self) {
+        self.record(Event::Start {
+            software_version: env!("CARGO_PKG_VERSION").to_string(),
+            date: chrono::Utc::now().to_rfc3339(),
+            arguments: std::env::args().collect(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::classification::Classifier;
+    use crate::fixer::Fixer;
+    use crate::journal::{Event, Journal};
+    use crate::objective::Objective;
+
+    #[test]
+    fn test_journal_lines() {
+        let path =
+            std::env::temp_dir().join(format!("mindsolver_journal_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let journal = Journal::create(path).unwrap();
+        journal.record_start();
+        journal.record(Event::Config {
+            classifier: Classifier::Cubie,
+            fixer: Fixer::Assignment,
+            objective: Objective::GroupMean,
+            threads: 4,
+            time_budget_s: None,
+            aggregation: None,
+            luminance_weight: 0.25,
+            unreliable_centres: vec!['U'],
+            calibration: None,
+            rescan: 0,
+        });
+        let clone = journal.clone();
+        clone.record(Event::SensorRead {
+            facelet: 4,
+            sample: [1., 2., 3.],
+        });
+        assert_eq!(journal.primitive("flip", || 42), 42);
+        journal.record(Event::End {
+            success: true,
+            outcome: "Cube solved".to_string(),
+        });
+        Journal::disabled().record(Event::Classification {
+            notation: String::new(),
+        });
+
+        let content = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).ok();
+        let lines = content
+            .lines()
+            .map(|x| serde_json::from_str::<serde_json::Value>(x).unwrap())
+            .collect::<Vec<_>>();
+        let events = lines
+            .iter()
+            .map(|x| x["event"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            ["start", "config", "sensor_read", "primitive", "end"]
+        );
+        assert_eq!(lines[1]["fixer"], "assignment");
+        assert_eq!(lines[1]["objective"], "group-mean");
+        assert_eq!(lines[1]["threads"], 4);
+        assert_eq!(lines[2]["facelet"], 4);
+        assert_eq!(lines[3]["name"], "flip");
+        assert!(lines[4]["elapsed_ms"].as_f64().unwrap() >= 0.);
+    }
+}
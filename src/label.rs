@@ -0,0 +1,150 @@
+use std::io::BufRead;
+
+use paris::{info, log, warn};
+
+use crate::classification::confidences;
+use crate::constants::FACES;
+use crate::cube::Cube;
+use crate::state_format::StateFormat;
+
+/// Number of least confidently classified facelets listed to the operator
+const SHOWN_UNCERTAIN: usize = 6;
+
+/// What the operator decided for a scan
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// Records the notation as the truth of the scan
+    Save(String),
+    /// Leaves the scan unlabelled
+    Skip,
+    /// Leaves the scan unlabelled and stops labelling
+    Quit,
+}
+
+/// Parses a facelet, given either by its index in the standard notation or by its face and position (1 to 9), such as `F3`
+fn parse_facelet(facelet: &str) -> Option<usize> {
+    if let Ok(index) = facelet.parse::<usize>() {
+        return (index < 54).then_some(index);
+    }
+    let mut chars = facelet.chars();
+    let face = chars.next()?.to_ascii_uppercase();
+    let face = FACES.iter().position(|x| *x == face)?;
+    let position = chars.as_str().parse::<usize>().ok()?;
+    (1..=9).contains(&position).then(|| 9 * face + position - 1)
+}
+
+/// Prints the proposed notation in the given format, and the facelets the classification is the least sure about
+fn show(cube: &Cube, notation: &str, format: &StateFormat) {
+    Cube::print_graphical(notation, format);
+    info!("Proposed notation is {}", format.export(notation));
+    let confidences = confidences(&cube.feature_points(), notation);
+    let mut uncertain = (0..54).collect::<Vec<_>>();
+    uncertain.sort_by(|a, b| confidences[*a].total_cmp(&confidences[*b]));
+    let uncertain = uncertain
+        .iter()
+        .take(SHOWN_UNCERTAIN)
+        .map(|i| format!("{i} ({}{})", FACES[i / 9], i % 9 + 1))
+        .collect::<Vec<_>>();
+    log!("Least confident facelets: {}", uncertain.join(", "));
+}
+
+/// Lets the operator correct the proposed notation of a scan line by line, until it is saved, skipped or labelling stops.
+/// A line is either a facelet and its colour (`12 F` or `R4 F`), a whole notation, `ok`, `skip` or `quit`.
+/// Colours and whole notations are typed in the given format. Only valid cube states can be saved.
+pub fn label_interactively(
+    input: &mut impl BufRead,
+    cube: &Cube,
+    proposal: String,
+    format: &StateFormat,
+) -> Decision {
+    let mut notation = proposal.chars().collect::<Vec<_>>();
+    show(cube, &proposal, format);
+    log!("Correct a facelet with `<facelet> <colour>`, where the facelet is an index or a face and position such as R4,");
+    log!("type a whole notation, `ok` to save, `skip` to leave the scan unlabelled or `quit` to stop labelling");
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line).unwrap_or(0) == 0 {
+            return Decision::Quit;
+        }
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            [] => continue,
+            ["ok"] => {
+                let current = notation.iter().collect::<String>();
+                match Cube::parse_state(&current) {
+                    Ok(truth) => return Decision::Save(truth),
+                    Err(e) => warn!("Cannot save an invalid cube state: {e}"),
+                }
+            }
+            ["skip"] => return Decision::Skip,
+            ["quit"] => return Decision::Quit,
+            [state] if state.chars().count() == 54 => match format.import(state) {
+                Ok(state) => {
+                    notation = state.chars().collect();
+                    show(cube, &state, format);
+                }
+                Err(e) => warn!("Invalid notation: {e}"),
+            },
+            [facelet, colour] => {
+                let colour = FACES
+                    .into_iter()
+                    .find(|x| colour.eq_ignore_ascii_case(&format.letter(*x).to_string()));
+                match (parse_facelet(facelet), colour) {
+                    (Some(index), Some(colour)) => {
+                        notation[index] = colour;
+                        show(cube, &notation.iter().collect::<String>(), format);
+                    }
+                    _ => warn!("Unknown facelet or colour"),
+                }
+            }
+            _ => warn!("Unknown command"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::cube::Cube;
+    use crate::label::{label_interactively, parse_facelet, Decision};
+    use crate::state_format::StateFormat;
+
+    #[test]
+    fn test_label_interactively() {
+        assert_eq!(parse_facelet("12"), Some(12));
+        assert_eq!(parse_facelet("r4"), Some(12));
+        assert_eq!(parse_facelet("B9"), Some(53));
+        assert_eq!(parse_facelet("U0"), None);
+        assert_eq!(parse_facelet("54"), None);
+
+        let cube = Cube::init();
+        let format = StateFormat::default();
+        let truth = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        let mut proposal = truth.chars().collect::<Vec<_>>();
+        proposal.swap(0, 9);
+        let proposal = proposal.into_iter().collect::<String>();
+        // the first ok is refused, as the notation is invalid
+        let mut input = Cursor::new("ok\nU1 u\n9 X\n9 r\nok\n");
+        assert_eq!(
+            label_interactively(&mut input, &cube, proposal.clone(), &format),
+            Decision::Save(truth.to_string())
+        );
+        let mut input = Cursor::new(format!("{}\nok\n", truth.to_lowercase()));
+        assert_eq!(
+            label_interactively(&mut input, &cube, proposal.clone(), &format),
+            Decision::Save(truth.to_string())
+        );
+        let mut input = Cursor::new("skip\n");
+        assert_eq!(
+            label_interactively(&mut input, &cube, proposal.clone(), &format),
+            Decision::Skip
+        );
+        let mut input = Cursor::new("1 F\n");
+        assert_eq!(
+            label_interactively(&mut input, &cube, proposal, &format),
+            Decision::Quit
+        );
+    }
+}
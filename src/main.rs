@@ -2,35 +2,82 @@ extern crate colored;
 extern crate ev3dev_lang_rust;
 extern crate paris;
 
+use std::fs::{read_dir, File};
+use std::io::{stdout, ErrorKind, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::sleep;
 use std::time::Duration;
 
-use clap::Parser;
+use clap::builder::RangedU64ValueParser;
+use clap::{Parser, Subcommand};
 use ev3dev_lang_rust::Ev3Result;
 use kewb::error::Error;
-use kewb::fs::write_table;
-use paris::{error, info, success};
+use kewb::fs::{read_table, write_table};
+use kewb::Solution;
+use paris::{error, info, log, success, warn};
 
+use crate::aggregation::Aggregation;
+use crate::batch::{batch, BatchFormat};
+use crate::bench::{bench, print_comparison, Pipeline};
+use crate::calibration::{Calibration, ColourModel};
+use crate::classification::{least_confident, Classifier, FeatureWeights};
+use crate::constants::FACES;
 use crate::cube::Cube;
+use crate::dataset::export_dataset;
 use crate::hardware::*;
-use fixer::find_optimal_fix;
+use crate::journal::{Event, Journal};
+use crate::label::{label_interactively, Decision};
+use crate::render::Net;
+use crate::scan_file::ScanMetadata;
+use crate::solution::{format_solution, SolutionFormat};
+use crate::state_format::{ColourScheme, FaceletOrder, StateFormat};
+use fixer::{fix_with, Fixer, FixerConfig, Progress};
+use objective::Objective;
 
+mod aggregation;
+mod assignment_fixer;
+mod batch;
+mod bench;
+mod calibration;
 mod classification;
 mod constants;
 mod cube;
+mod dataset;
+mod diagnosis;
 mod fixer;
 mod hardware;
+mod journal;
+mod label;
+mod objective;
+mod render;
+mod report;
+mod scan_file;
+mod solution;
+mod state_format;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// File source if using a previous scan file. Will skip scan
     #[arg(short, long)]
     file: Option<String>,
 
+    /// Notation of the cube state, such as one read from an app. Will skip scan, classification and fixing.
+    /// The cube must be placed in the robot as it is before a scan, with the F face down and the R face right of the sensor
+    #[arg(long, conflicts_with_all = ["file", "scramble"])]
+    state: Option<String>,
+
+    /// Moves scrambling a solved cube, such as "R U R' U2". Will skip scan, classification and fixing
+    #[arg(long, conflicts_with_all = ["file", "state"], allow_hyphen_values = true)]
+    scramble: Option<String>,
+
     /// Number of color sensor scans per facelet
-    #[arg(long, default_value_t = 5)]
+    #[arg(long, default_value_t = 5, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
     iteration: usize,
 
     /// Movement between each color sensor scan
@@ -48,66 +95,420 @@ struct Args {
     /// Sleep duration between each color sensor scan (in ms)
     #[arg(long, default_value_t = 20)]
     sleep: u32,
+
+    /// Name of the robot, saved with the scan
+    #[arg(long)]
+    robot: Option<String>,
+
+    /// Model of the scanned cube, saved with the scan
+    #[arg(long)]
+    cube_model: Option<String>,
+
+    /// Classification technique used on the scanned colours
+    #[arg(long, value_enum, default_value_t = Classifier::Greedy, global = true)]
+    classifier: Classifier,
+
+    /// Method used to reduce the scans of a facelet into a single reading.
+    /// When loading a scan file, recomputes the readings from the raw samples it contains
+    #[arg(long, value_enum, global = true)]
+    aggregation: Option<Aggregation>,
+
+    /// Technique used to turn the classified notation into a valid one
+    #[arg(long, value_enum, default_value_t = Fixer::Search, global = true)]
+    fixer: Fixer,
+
+    /// Quantity minimised by the search fixer, the only fixer accepting it. The likelihood needs a calibration profile
+    #[arg(long, value_enum, default_value_t = Objective::GroupMean, global = true)]
+    objective: Objective,
+
+    /// Number of threads used by the fixer. Defaults to the number of available cores
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+
+    /// Time after which the fixer stops and keeps the best valid state found so far (in s)
+    #[arg(long, global = true)]
+    time_budget: Option<f64>,
+
+    /// Saves a JSON report of what the fixer changed to the given file
+    #[arg(long)]
+    report: Option<String>,
+
+    /// Saves the unfolded net of the scan, with the reading, colour and confidence of every facelet,
+    /// or of the given state, to the given file as SVG. Other formats, such as PNG, are not supported
+    #[arg(long)]
+    net: Option<String>,
+
+    /// Order of the facelets of the cube states given and printed
+    #[arg(long, value_enum, default_value_t = FaceletOrder::Urfdlb, global = true)]
+    facelet_order: FaceletOrder,
+
+    /// Colours of the U, R, F, D, L and B faces, such as WBRYGO, among W, Y, R, O, G and B.
+    /// Cube states are then given and printed with these colours instead of face letters
+    #[arg(long, value_parser = ColourScheme::parse, global = true)]
+    colour_scheme: Option<ColourScheme>,
+
+    /// Format the solution is printed in
+    #[arg(long, value_enum, default_value_t = SolutionFormat::Kewb)]
+    solution_format: SolutionFormat,
+
+    /// Saves the robot primitives applying the solution to the given file, one per line
+    #[arg(long)]
+    primitives: Option<String>,
+
+    /// Records every stage of the run, from the sensor reads to the robot primitives, to the given JSON lines file
+    #[arg(long)]
+    journal: Option<String>,
+
+    /// Faces whose centre reading is not trusted by the cluster classifier, such as a logo centre
+    #[arg(long, value_delimiter = ',', value_parser = parse_face, global = true)]
+    unreliable_centres: Vec<char>,
+
+    /// Weight of the luminance relative to the chromaticity when comparing colours
+    #[arg(long, default_value_t = FeatureWeights::default().luminance, global = true)]
+    luminance_weight: f64,
+
+    /// Calibration profile used to correct the sensor bias before classification
+    #[arg(long, global = true)]
+    calibration: Option<String>,
+
+    /// Number of least confidently classified facelets to scan again before fixing. Needs a scan by the robot
+    #[arg(long, default_value_t = 0, conflicts_with_all = ["file", "state", "scramble"])]
+    rescan: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Estimates the sensor bias from labelled scans and saves it as a calibration profile
+    Calibrate {
+        /// Scan files, or directories of scan files, to estimate the bias from
+        #[arg(required = true)]
+        scans: Vec<String>,
+
+        /// True notation of the scanned cube, for the scan files that do not record it
+        #[arg(long, default_value = SOLVED_NOTATION)]
+        truth: String,
+
+        /// Estimates a bias for every facelet instead of every position class
+        #[arg(long)]
+        per_index: bool,
+
+        /// File to save the calibration profile to
+        #[arg(short, long, default_value = "calibration.json")]
+        output: String,
+    },
+    /// Classifies and fixes scans of a known cube and reports the accuracy of the pipeline
+    Bench {
+        /// Scan files, or directories of scan files, to run the pipeline on
+        #[arg(required = true)]
+        scans: Vec<String>,
+
+        /// True notation of the scanned cube, for the scan files that do not record it
+        #[arg(long, default_value = SOLVED_NOTATION)]
+        truth: String,
+
+        /// Classifier of a second pipeline to compare with
+        #[arg(long, value_enum)]
+        compare_classifier: Option<Classifier>,
+
+        /// Fixer of a second pipeline to compare with
+        #[arg(long, value_enum)]
+        compare_fixer: Option<Fixer>,
+
+        /// Aggregation of a second pipeline to compare with
+        #[arg(long, value_enum)]
+        compare_aggregation: Option<Aggregation>,
+
+        /// Luminance weight of a second pipeline to compare with
+        #[arg(long)]
+        compare_luminance_weight: Option<f64>,
+
+        /// Fixer objective of a second pipeline to compare with
+        #[arg(long, value_enum)]
+        compare_objective: Option<Objective>,
+    },
+    /// Classifies, fixes and solves scan files, writing a CSV or JSON line for every file
+    Batch {
+        #[arg(required = true, help = ROW_SCANS_HELP)]
+        scans: Vec<String>,
+
+        /// Format of the rows
+        #[arg(long, value_enum, default_value_t = BatchFormat::Csv)]
+        format: BatchFormat,
+
+        /// File to write the rows to, instead of the standard output
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Records the true notation of scan files, converting them to the versioned format.
+    /// The operator corrects the fixed classification of every scan, unless the truth is given
+    Label {
+        /// Scan files, or directories of scan files, to label
+        #[arg(required = true)]
+        scans: Vec<String>,
+
+        /// True notation of all the scanned cubes. Skips the interactive correction
+        #[arg(long)]
+        truth: Option<String>,
+
+        /// Labels again the scans that already record their true notation
+        #[arg(long)]
+        relabel: bool,
+    },
+    /// Classifies and fixes scan files, writing a CSV or JSON line for every facelet with its readings and labels
+    ExportDataset {
+        #[arg(required = true, help = ROW_SCANS_HELP)]
+        scans: Vec<String>,
+
+        /// True notation of the scanned cube, for the scan files that do not record it
+        #[arg(long)]
+        truth: Option<String>,
+
+        /// Format of the rows
+        #[arg(long, value_enum, default_value_t = BatchFormat::Csv)]
+        format: BatchFormat,
+
+        /// File to write the rows to, instead of the standard output
+        #[arg(short, long)]
+        output: Option<String>,
+    },
 }
 
-/// creates the kociemba cache file if it does not exist
-fn create_cache() -> Result<(), Error> {
+/// Help of the scans of the subcommands writing a row per scan file, listed by [scan_files]
+const ROW_SCANS_HELP: &str = "Scan files, or directories searched recursively for scan files. \
+    Every file of the directories but hidden ones is read as a scan, the others giving failed rows";
+
+const SOLVED_NOTATION: &str = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+
+/// Set while the fixer runs, so that ctrl-c cancels the search instead of exiting
+static FIXING: AtomicBool = AtomicBool::new(false);
+
+/// Handles ctrl-c: cancels the fixer if it is running, otherwise exits, shutting down the hardware if needed
+fn set_ctrlc_handler(cancel: Arc<AtomicBool>, hardware: bool, journal: Journal) {
+    ctrlc::set_handler(move || {
+        if FIXING.load(Ordering::SeqCst) {
+            warn!("Cancelling the fixer...");
+            cancel.store(true, Ordering::SeqCst);
+            return;
+        }
+        journal.record(Event::End {
+            success: false,
+            outcome: "Interrupted".to_string(),
+        });
+        if hardware {
+            Hardware::shutdown().expect("Could not shutdown hardware");
+        }
+        std::process::exit(0);
+    })
+    .expect("Could not define ctlr-c handler");
+}
+
+/// creates the kociemba cache file if it does not exist.
+/// Nothing is logged when `quiet` is set, so that the rows written to the standard output stay clean
+fn create_cache(quiet: bool) -> Result<(), Error> {
     if !Path::new("./cache_file").exists() {
-        info!("Creating cache...");
+        if !quiet {
+            info!("Creating cache...");
+        }
         write_table("./cache_file")?;
     }
     Ok(())
 }
 
+/// True if the command writes rows to the standard output
+fn writes_rows_to_stdout(args: &Args) -> bool {
+    matches!(
+        args.command,
+        Some(Command::Batch { output: None, .. } | Command::ExportDataset { output: None, .. })
+    )
+}
+
 fn main() -> Ev3Result<()> {
-    if let Err(e) = create_cache() {
+    let args = Args::parse();
+    if let Err(e) = create_cache(writes_rows_to_stdout(&args)) {
         error!("Could not create cache: {e}\nWill try to continue...")
     };
-    let args = Args::parse();
+    match &args.command {
+        Some(Command::Calibrate {
+            scans,
+            truth,
+            per_index,
+            output,
+        }) => {
+            let truth = parse_truth(&args, truth);
+            calibrate(scans, &truth, *per_index, output, args.luminance_weight);
+            return Ok(());
+        }
+        Some(Command::Bench {
+            scans,
+            truth,
+            compare_classifier,
+            compare_fixer,
+            compare_aggregation,
+            compare_luminance_weight,
+            compare_objective,
+        }) => {
+            let files = scan_files(scans);
+            let pipeline = exit_on_error(pipeline(&args));
+            let mut pipelines = vec![pipeline.clone()];
+            if compare_classifier.is_some()
+                || compare_fixer.is_some()
+                || compare_aggregation.is_some()
+                || compare_luminance_weight.is_some()
+                || compare_objective.is_some()
+            {
+                let mut config = pipeline.config.clone();
+                config.objective = compare_objective.unwrap_or(config.objective);
+                let fixer = compare_fixer.unwrap_or(pipeline.fixer);
+                exit_on_error(check_objective(fixer, &config));
+                pipelines.push(Pipeline {
+                    classifier: compare_classifier.unwrap_or(pipeline.classifier),
+                    fixer,
+                    aggregation: compare_aggregation.or(pipeline.aggregation),
+                    luminance_weight: compare_luminance_weight.unwrap_or(pipeline.luminance_weight),
+                    config,
+                    ..pipeline
+                });
+            }
+            let results = pipelines
+                .iter()
+                .map(|x| (x.name(), bench(x, &files, truth)))
+                .collect::<Vec<_>>();
+            print_comparison(&results);
+            return Ok(());
+        }
+        Some(Command::Batch {
+            scans,
+            format,
+            output,
+        }) => {
+            let files = scan_files(scans);
+            let table = read_table("./cache_file").expect("Could not load cache");
+            let pipeline = exit_on_error(pipeline(&args));
+            write_rows(output.as_deref(), |w| {
+                batch(&pipeline, &table, &files, *format, w)
+            });
+            return Ok(());
+        }
+        Some(Command::Label {
+            scans,
+            truth,
+            relabel,
+        }) => {
+            label(&args, scans, truth.as_deref(), *relabel);
+            return Ok(());
+        }
+        Some(Command::ExportDataset {
+            scans,
+            truth,
+            format,
+            output,
+        }) => {
+            let files = scan_files(scans);
+            let truth = truth.as_deref().map(|x| parse_truth(&args, x));
+            let pipeline = exit_on_error(pipeline(&args));
+            let truth = truth.as_deref();
+            write_rows(output.as_deref(), |w| {
+                export_dataset(&pipeline, &files, truth, *format, w)
+            });
+            return Ok(());
+        }
+        None => {}
+    }
+    let format = state_format(&args);
+    let given_notation = given_notation(&args);
+    let journal = open_journal(&args);
+    journal.record_start();
+    if let (true, Some(notation)) = (args.nosolve, &given_notation) {
+        save_net(&args, || Net::from_notation(notation, &format));
+        let mut cube = Cube::init();
+        Hardware::set_orientation(&mut cube, START_ORIENTATION);
+        find_solution(&args, &journal, notation.clone(), &cube, false);
+        journal.record(Event::End {
+            success: true,
+            outcome: "Solution found".to_string(),
+        });
+        return Ok(());
+    }
+    let calibration = exit_on_error(load_calibration(&args));
+    let config = exit_on_error(fixer_config(&args, calibration.as_ref()));
+    record_config(&journal, &args, &config);
     if args.nosolve && args.file.is_some() {
         // we can skip hardware initialisation
-        no_hardware(args);
+        no_hardware(args, &journal, calibration, config);
         return Ok(());
     }
 
+    let result = run_robot(&args, &journal, calibration, config, given_notation);
+    if let Err(e) = &result {
+        journal.record(Event::End {
+            success: false,
+            outcome: format!("Hardware error: {e}"),
+        });
+    }
+    result
+}
+
+/// Scans the cube with the robot, unless it is loaded or given, then fixes, solves and applies the solution
+fn run_robot(
+    args: &Args,
+    journal: &Journal,
+    calibration: Option<Calibration>,
+    config: FixerConfig,
+    given_notation: Option<String>,
+) -> Ev3Result<()> {
+    let format = state_format(args);
     let mut hw = Hardware::init(
         Duration::from_millis(args.sleep as u64),
         args.movement,
         args.iteration,
+        args.aggregation.unwrap_or_default(),
     )?;
-    ctrlc::set_handler(move || {
-        Hardware::shutdown().expect("Could not shutdown hardware");
-        std::process::exit(0);
-    })
-    .expect("Could not define ctlr-c handler");
-    let mut cube = Cube::init();
+    hw.journal = journal.clone();
+    set_ctrlc_handler(config.cancel.clone(), true, journal.clone());
+    let mut cube = init_cube(args);
 
     info!("Resetting sensor arm...");
     hw.reset_sensor_position()?;
 
-    if args.file.is_none() {
-        info!("Starting cube scan.");
-        hw.scan_cube(&mut cube)?;
-        if args.save {
-            cube.export();
-        }
+    let fixed_notation = if let Some(notation) = given_notation {
+        // the cube is not scanned, so it is still as it was placed in the robot
+        Hardware::set_orientation(&mut cube, START_ORIENTATION);
+        info!("Cube string is: {}", format.export(&notation));
+        save_net(args, || Net::from_notation(&notation, &format));
+        notation
     } else {
-        cube.import(args.file.unwrap())
-            .expect("Could not load scan file");
-    }
-
-    let cube_notation = cube.to_notation();
-    info!("Unfixed cube string is: {}", cube_notation);
-    Cube::print_graphical(cube_notation.as_str());
-    let (score, fixed_notation) = find_optimal_fix(&cube.facelet_rgb_values, cube_notation);
-    success!(
-        "Cube string fixed with {score} accuracy (lower is better) is: {}",
+        if args.file.is_some() {
+            load_scan(args, &mut cube);
+        } else {
+            info!("Starting cube scan.");
+            cube.metadata = ScanMetadata {
+                iteration: Some(args.iteration),
+                movement: Some(args.movement),
+                sleep: Some(args.sleep),
+                aggregation: Some(args.aggregation.unwrap_or_default()),
+                robot: args.robot.clone(),
+                cube_model: args.cube_model.clone(),
+                ..ScanMetadata::now()
+            };
+            hw.scan_cube(&mut cube)?;
+            if args.rescan > 0 {
+                let notation = cube.to_notation_with(args.classifier);
+                let facelets = least_confident(&cube.feature_points(), &notation, args.rescan);
+                hw.rescan(&mut cube, &facelets)?;
+            }
+            if args.save {
+                cube.export();
+            }
+        }
+        let Some(fixed_notation) =
+            fix_cube(args, journal, &mut cube, calibration.as_ref(), &config)
+        else {
+            Hardware::shutdown()?;
+            return Ok(());
+        };
         fixed_notation
-    );
-    Cube::print_graphical(fixed_notation.as_str());
-
-    let solution = Cube::solve(fixed_notation);
-    info!("Solution is {}", solution);
+    };
+    let solution = find_solution(args, journal, fixed_notation, &cube, hw.locked);
     if !args.nosolve {
         for part in solution.get_all_moves() {
             hw.apply_solution_part(part.to_string(), &mut cube)?;
@@ -116,24 +517,416 @@ fn main() -> Ev3Result<()> {
             hw.unlock_cube()?;
         }
         success!("Cube solved! I hope you enjoyed :D");
+        journal.record(Event::End {
+            success: true,
+            outcome: "Cube solved".to_string(),
+        });
+    } else {
+        journal.record(Event::End {
+            success: true,
+            outcome: "Solution found".to_string(),
+        });
     }
     sleep(Duration::from_secs(1)); // waiting for the flipper to stabilize
     Hardware::shutdown()?;
     Ok(())
 }
 
+/// Returns the notation given with `--state` or `--scramble`, if any. Exits if it cannot be solved
+fn given_notation(args: &Args) -> Option<String> {
+    let notation = if let Some(state) = &args.state {
+        state_format(args)
+            .import(state)
+            .and_then(|x| Cube::parse_state(&x))
+    } else if let Some(scramble) = &args.scramble {
+        Cube::scrambled(scramble)
+    } else {
+        return None;
+    };
+    match notation {
+        Ok(notation) => Some(notation),
+        Err(e) => {
+            error!("Invalid cube state: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Opens the journal given with `--journal`, if any
+fn open_journal(args: &Args) -> Journal {
+    match &args.journal {
+        Some(path) => Journal::create(path).expect("Could not create journal"),
+        None => Journal::disabled(),
+    }
+}
+
+/// Prints the solution in the format given with `--solution-format`, and saves the robot primitives applying it,
+/// from the given orientation of the cube and state of the flipper, to the file given with `--primitives`, if any
+fn print_solution(args: &Args, solution: &Solution, cube: &Cube, locked: bool) {
+    let primitives = plan_solution(solution, Hardware::orientation(cube), locked);
+    info!(
+        "Solution is {}",
+        format_solution(solution, args.solution_format, &primitives)
+    );
+    if let Some(path) = &args.primitives {
+        let listing = primitives
+            .iter()
+            .map(|x| format!("{x}\n"))
+            .collect::<String>();
+        std::fs::write(path, listing).expect("Could not save robot primitives");
+        info!("Saved robot primitives to {path}");
+    }
+}
+
+/// Records the settings of the run, once its arguments are resolved
+fn record_config(journal: &Journal, args: &Args, config: &FixerConfig) {
+    journal.record(Event::Config {
+        classifier: args.classifier,
+        fixer: args.fixer,
+        objective: config.objective,
+        threads: config.threads,
+        time_budget_s: config.time_budget.map(|x| x.as_secs_f64()),
+        aggregation: args.aggregation,
+        luminance_weight: args.luminance_weight,
+        unreliable_centres: args.unreliable_centres.clone(),
+        calibration: args.calibration.clone(),
+        rescan: args.rescan,
+    });
+}
+
+/// Records the solution found for the cube
+fn record_solution(journal: &Journal, solution: &Solution) {
+    journal.record(Event::Solution {
+        moves: solution.to_string(),
+        length: solution.len(),
+    });
+}
+
+/// Builds the format of the cube states given and printed from the arguments
+fn state_format(args: &Args) -> StateFormat {
+    StateFormat {
+        order: args.facelet_order,
+        scheme: args.colour_scheme,
+    }
+}
+
+/// Saves the net to the file given with `--net`, if any
+fn save_net(args: &Args, net: impl FnOnce() -> Net) {
+    if let Some(path) = &args.net {
+        net().save(path).expect("Could not save cube net");
+        info!("Saved cube net to {path}");
+    }
+}
+
+/// Loads the calibration profile given with `--calibration`, if any
+fn load_calibration(args: &Args) -> Result<Option<Calibration>, String> {
+    args.calibration
+        .as_ref()
+        .map(|x| {
+            Calibration::load(x).map_err(|e| format!("Could not load calibration profile: {e}"))
+        })
+        .transpose()
+}
+
+/// Checks that the objective of the settings can be used by the fixer
+fn check_objective(fixer: Fixer, config: &FixerConfig) -> Result<(), String> {
+    if config.objective != Objective::default() && fixer != Fixer::Search {
+        return Err("The objective is only used by the search fixer".to_string());
+    }
+    if config.objective == Objective::Likelihood && config.model.is_none() {
+        return Err(
+            "The likelihood objective needs a calibration profile with a colour model".to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Builds the fixer settings from the arguments and the loaded calibration profile
+fn fixer_config(args: &Args, calibration: Option<&Calibration>) -> Result<FixerConfig, String> {
+    let mut config = FixerConfig::default();
+    if let Some(threads) = args.threads {
+        config.threads = threads;
+    }
+    config.time_budget = args.time_budget.map(Duration::from_secs_f64);
+    config.objective = args.objective;
+    config.model = calibration.and_then(|x| x.model.clone());
+    check_objective(args.fixer, &config)?;
+    config.progress = Some(Arc::new(|progress: &Progress| {
+        let best = progress
+            .best_score
+            .map_or("none yet".to_string(), |x| format!("{x:.3}"));
+        log!(
+            "Explored {} candidates at depth {} in {:.2?}, best score is {best}",
+            progress.candidates,
+            progress.depth,
+            progress.elapsed
+        );
+    }));
+    Ok(config)
+}
+
+/// Builds the classification and fixing settings from the arguments, for batch processing
+fn pipeline(args: &Args) -> Result<Pipeline, String> {
+    let calibration = load_calibration(args)?;
+    let mut config = fixer_config(args, calibration.as_ref())?;
+    config.progress = None;
+    Ok(Pipeline {
+        classifier: args.classifier,
+        fixer: args.fixer,
+        aggregation: args.aggregation,
+        luminance_weight: args.luminance_weight,
+        unreliable_centres: args.unreliable_centres.clone(),
+        calibration,
+        config,
+    })
+}
+
+/// Writes rows to the given file, or to the standard output if there is none
+fn write_rows(output: Option<&str>, write: impl FnOnce(&mut dyn Write) -> std::io::Result<()>) {
+    let result = match output {
+        Some(path) => write(&mut File::create(path).expect("Could not create output file")),
+        None => write(&mut stdout().lock()),
+    };
+    match result {
+        // the reader of the output, such as `head`, may stop early
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => {}
+        result => result.expect("Could not write rows"),
+    }
+}
+
+/// Returns the value, or prints the error and exits. Only used before the hardware is initialised
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        error!("{e}");
+        std::process::exit(1);
+    })
+}
+
+/// Lists the given scan files, and the files of the given directories and their subdirectories, in alphabetical order.
+/// Hidden files and directories, such as `.gitkeep`, are skipped
+fn scan_files(paths: &[String]) -> Vec<String> {
+    let mut files = vec![];
+    for path in paths {
+        if Path::new(path).is_dir() {
+            let entries = read_dir(path)
+                .expect("Could not read scan directory")
+                .flatten()
+                .filter(|x| !x.file_name().to_string_lossy().starts_with('.'))
+                .map(|x| x.path().to_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            files.extend(scan_files(&entries));
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+    files
+}
+
 /// Ran when the user does not want to use the hardware. Used for testing purposes.
-fn no_hardware(args: Args) {
+fn no_hardware(
+    args: Args,
+    journal: &Journal,
+    calibration: Option<Calibration>,
+    config: FixerConfig,
+) {
+    let mut cube = init_cube(&args);
+    load_scan(&args, &mut cube);
+    set_ctrlc_handler(config.cancel.clone(), false, journal.clone());
+    let Some(fixed_notation) = fix_cube(&args, journal, &mut cube, calibration.as_ref(), &config)
+    else {
+        return;
+    };
+    find_solution(&args, journal, fixed_notation, &cube, false);
+    journal.record(Event::End {
+        success: true,
+        outcome: "Solution found".to_string(),
+    });
+}
+
+/// Creates a cube scored with the classification settings of the arguments
+fn init_cube(args: &Args) -> Cube {
     let mut cube = Cube::init();
-    cube.import(args.file.unwrap())
-        .expect("Could not load scan file");
-    let cube_notation = cube.to_notation();
-    let (score, fixed_notation) = find_optimal_fix(&cube.facelet_rgb_values, cube_notation);
+    cube.feature_weights.luminance = args.luminance_weight;
+    cube.unreliable_centres = args.unreliable_centres.clone();
+    cube
+}
+
+/// Loads the scan file given with `--file` into the cube, recomputing its readings with the aggregation, if any
+fn load_scan(args: &Args, cube: &mut Cube) {
+    let file = args.file.clone().expect("No scan file given");
+    cube.import(file).expect("Could not load scan file");
+    info!("Loaded scan from file");
+    if let Some(aggregation) = args.aggregation {
+        cube.reaggregate(aggregation)
+            .expect("Could not recompute the readings");
+    }
+}
+
+/// Corrects the readings of the loaded or scanned cube with the calibration, classifies and fixes them,
+/// recording every step in the journal and saving the net and the report given in the arguments.
+/// Returns the fixed notation, or None once the failure to fix it is reported and recorded
+fn fix_cube(
+    args: &Args,
+    journal: &Journal,
+    cube: &mut Cube,
+    calibration: Option<&Calibration>,
+    config: &FixerConfig,
+) -> Option<String> {
+    let format = state_format(args);
+    if let Some(calibration) = calibration {
+        calibration
+            .correct(cube)
+            .expect("Could not apply calibration profile");
+    }
+    let cube_notation = cube.to_notation_with(args.classifier);
+    journal.record(Event::Classification {
+        notation: cube_notation.clone(),
+    });
+    info!("Unfixed cube string is: {}", format.export(&cube_notation));
+    Cube::print_graphical(cube_notation.as_str(), &format);
+    save_net(args, || Net::from_scan(cube, &cube_notation, &format));
+    FIXING.store(true, Ordering::SeqCst);
+    let fixed = fix_with(args.fixer, &cube.feature_points(), cube_notation, config);
+    FIXING.store(false, Ordering::SeqCst);
+    let report = match fixed {
+        Ok(report) => report,
+        Err(e) => {
+            error!("Could not fix the cube: {e}");
+            journal.record(Event::End {
+                success: false,
+                outcome: format!("Could not fix the cube: {e}"),
+            });
+            return None;
+        }
+    };
+    journal.record(Event::Fix {
+        report: report.clone(),
+    });
+    report.print();
+    if let Some(path) = &args.report {
+        report.save(path).expect("Could not save fix report");
+    }
     success!(
-        "Cube string fixed with {score} accuracy (lower is better) is: {}",
-        fixed_notation
+        "Cube string fixed with {} accuracy (lower is better) is: {}",
+        report.score,
+        format.export(&report.fixed)
+    );
+    Some(report.fixed)
+}
+
+/// Prints the valid notation and solves it, printing and recording the solution
+fn find_solution(
+    args: &Args,
+    journal: &Journal,
+    notation: String,
+    cube: &Cube,
+    locked: bool,
+) -> Solution {
+    Cube::print_graphical(notation.as_str(), &state_format(args));
+    let solution = Cube::solve(notation);
+    print_solution(args, &solution, cube, locked);
+    record_solution(journal, &solution);
+    solution
+}
+
+/// Checks the true notation given by the user, in the state format of the arguments. Exits if it is invalid
+fn parse_truth(args: &Args, truth: &str) -> String {
+    let truth = state_format(args)
+        .import(truth)
+        .and_then(|x| Cube::parse_state(&x));
+    truth.unwrap_or_else(|e| {
+        error!("Invalid truth: {e}");
+        std::process::exit(1);
+    })
+}
+
+/// Parses the letter of a face, in any case
+fn parse_face(face: &str) -> Result<char, String> {
+    match face.trim().to_uppercase().chars().collect::<Vec<_>>()[..] {
+        [face] if FACES.contains(&face) => Ok(face),
+        _ => Err(format!(
+            "{face} is not one of the faces U, R, F, D, L and B"
+        )),
+    }
+}
+
+/// Records the true notation of the given scans: the given one if any, otherwise the one the operator confirms
+fn label(args: &Args, paths: &[String], truth: Option<&str>, relabel: bool) {
+    let truth = truth.map(|x| parse_truth(args, x));
+    let pipeline = exit_on_error(pipeline(args));
+    let mut input = std::io::stdin().lock();
+    let mut labelled = 0;
+    for file in scan_files(paths) {
+        let mut cube = Cube::init();
+        cube.import(file.clone()).expect("Could not load scan file");
+        if cube.truth.is_some() && !relabel {
+            log!("Skipping {file}, which is already labelled");
+            continue;
+        }
+        let decision = match &truth {
+            Some(truth) => Decision::Save(truth.clone()),
+            None => {
+                info!("Labelling {file}");
+                let (classified, fixed, recorded) = exit_on_error(pipeline.run(&file));
+                let proposal = recorded.or(fixed).unwrap_or(classified);
+                label_interactively(&mut input, &cube, proposal, &state_format(args))
+            }
+        };
+        match decision {
+            Decision::Save(truth) => {
+                cube.truth = Some(truth);
+                cube.save(&file).expect("Could not save scan file");
+                labelled += 1;
+            }
+            Decision::Skip => {}
+            Decision::Quit => break,
+        }
+    }
+    success!("Labelled {labelled} scans");
+}
+
+/// Estimates the sensor bias from the given labelled scans and saves the calibration profile.
+/// The colour model is estimated from the features of the scans once the bias is removed.
+fn calibrate(paths: &[String], truth: &str, per_index: bool, output: &str, luminance_weight: f64) {
+    let files = scan_files(paths);
+    let mut cubes = vec![];
+    for file in files {
+        let mut cube = Cube::init();
+        cube.feature_weights.luminance = luminance_weight;
+        cube.import(file).expect("Could not load scan file");
+        cubes.push(cube);
+    }
+    let unit = cubes.first().map(|x| x.unit).unwrap_or_default();
+    if cubes.iter().any(|x| x.unit != unit) {
+        error!("Cannot calibrate from scans holding both raw and normalised readings");
+        std::process::exit(1);
+    }
+    let truths = cubes
+        .iter()
+        .map(|cube| cube.truth.clone().unwrap_or(truth.to_string()))
+        .collect::<Vec<_>>();
+    let scans = cubes
+        .iter()
+        .zip(&truths)
+        .map(|(cube, truth)| (cube.facelet_rgb_values.clone(), truth.clone()))
+        .collect::<Vec<_>>();
+    let mut calibration = Calibration::estimate(&scans, per_index);
+    calibration.unit = Some(unit);
+    let features = cubes
+        .iter_mut()
+        .zip(truths)
+        .map(|(cube, truth)| {
+            calibration.apply(&mut cube.facelet_rgb_values);
+            (cube.feature_points(), truth)
+        })
+        .collect::<Vec<_>>();
+    calibration.model = Some(ColourModel::estimate(&features));
+    calibration
+        .save(output)
+        .expect("Could not save calibration profile");
+    success!(
+        "Saved calibration profile from {} scans to {output}",
+        scans.len()
     );
-    Cube::print_graphical(fixed_notation.as_str());
-    let solution = Cube::solve(fixed_notation);
-    info!("Solution is {}", solution);
 }
@@ -0,0 +1,278 @@
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::calibration::ColourModel;
+use crate::classification::FeaturePoint;
+use crate::constants::FACES;
+use crate::fixer::Move;
+
+/// Quantity minimised by the search fixer
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Objective {
+    /// Squared distances between the facelets and the mean of their colour.
+    /// Unlike the [calculate_score](crate::fixer::calculate_score) reported by every fixer, it is updated in constant time
+    #[default]
+    GroupMean,
+    /// Negative log-likelihood of the facelets under the colour model of the calibration profile
+    Likelihood,
+    /// Distances between the facelets and the centre reading of their colour
+    CentreDistance,
+    /// Variance within the colours divided by the variance between the colour means
+    VarianceRatio,
+}
+
+/// Features of a scan, along with everything needed to score its notations with an objective
+pub struct Scorer {
+    objective: Objective,
+    features: Vec<[f64; 4]>,
+    /// Cost of giving every colour of [FACES] to every facelet, for objectives summing a cost per facelet.
+    /// The lowest cost of every facelet is subtracted so that scores are never negative
+    costs: Vec<[f64; 6]>,
+}
+
+impl Scorer {
+    /// Prepares the scoring of the notations of the scan.
+    /// The likelihood objective needs a colour model, whose colours are matched to the faces by their centres.
+    pub fn init(
+        rgb_values: &[FeaturePoint],
+        objective: Objective,
+        model: Option<&ColourModel>,
+    ) -> Self {
+        let costs = match objective {
+            Objective::GroupMean | Objective::VarianceRatio => vec![[0.; 6]; rgb_values.len()],
+            Objective::CentreDistance => rgb_values
+                .iter()
+                .map(|point| [0, 1, 2, 3, 4, 5].map(|i| point.distance_to(&rgb_values[9 * i + 4])))
+                .collect(),
+            Objective::Likelihood => {
+                let faces = model
+                    .expect("The likelihood objective needs a colour model")
+                    .faces(rgb_values);
+                rgb_values
+                    .iter()
+                    .map(|point| {
+                        [0, 1, 2, 3, 4, 5].map(|i| faces[i].negative_log_likelihood(point))
+                    })
+                    .collect()
+            }
+        };
+        let costs = costs
+            .into_iter()
+            .map(|x: [f64; 6]| {
+                let min = x.iter().copied().fold(f64::INFINITY, f64::min);
+                x.map(|cost| cost - min)
+            })
+            .collect();
+        Scorer {
+            objective,
+            features: rgb_values.iter().map(|x| x.to_features()).collect(),
+            costs,
+        }
+    }
+
+    /// Scores the notation from scratch
+    pub fn score(&self, notation: &str) -> f64 {
+        IncrementalScore::init(self, &notation.chars().collect_vec()).score(self)
+    }
+}
+
+/// Running sums of the features of the facelets of a colour
+#[derive(Clone, Copy, Default)]
+struct Group {
+    sum: [f64; 4],
+    squares: f64,
+    count: usize,
+}
+
+impl Group {
+    fn update(&mut self, features: &[f64; 4], sign: f64) {
+        for (sum, x) in self.sum.iter_mut().zip(features) {
+            *sum += sign * x;
+        }
+        self.squares += sign * features.iter().map(|x| x.powi(2)).sum::<f64>();
+    }
+
+    fn add(&mut self, features: &[f64; 4]) {
+        self.update(features, 1.);
+        self.count += 1;
+    }
+
+    fn remove(&mut self, features: &[f64; 4]) {
+        self.update(features, -1.);
+        self.count -= 1;
+    }
+
+    /// Sum of the squared distances between the facelets and their mean
+    fn squared_error(&self) -> f64 {
+        if self.count == 0 {
+            return 0.;
+        }
+        // rounding errors could make it slightly negative
+        (self.squares - self.sum.iter().map(|x| x.powi(2)).sum::<f64>() / self.count as f64).max(0.)
+    }
+
+    fn mean(&self) -> [f64; 4] {
+        self.sum.map(|x| x / self.count as f64)
+    }
+}
+
+/// Score of a notation that is cheaply updated when a [Move] is applied.
+/// Every objective only depends on running sums of the features of every colour, giving the colour means,
+/// or on a sum of costs per facelet.
+/// Colours do not need to appear 9 times, so that notations with unbalanced colour counts can be scored too.
+#[derive(Clone, Copy)]
+pub struct IncrementalScore {
+    /// Index in [FACES] of the colour of every facelet
+    notation: [usize; 54],
+    groups: [Group; 6],
+    /// Sum of the costs of the colours of the facelets
+    cost: f64,
+}
+
+impl IncrementalScore {
+    pub fn init(scorer: &Scorer, chars: &[char]) -> Self {
+        let mut score = IncrementalScore {
+            notation: [0; 54],
+            groups: [Group::default(); 6],
+            cost: 0.,
+        };
+        for (i, features) in scorer.features.iter().enumerate() {
+            let colour = FACES
+                .iter()
+                .position(|x| *x == chars[i])
+                .expect("Notation holds an unknown colour");
+            score.notation[i] = colour;
+            score.groups[colour].add(features);
+            score.cost += scorer.costs[i][colour];
+        }
+        score
+    }
+
+    pub fn score(&self, scorer: &Scorer) -> f64 {
+        let within = || self.groups.iter().map(Group::squared_error).sum::<f64>();
+        match scorer.objective {
+            Objective::GroupMean => within(),
+            Objective::Likelihood | Objective::CentreDistance => self.cost,
+            Objective::VarianceRatio => {
+                let means = self
+                    .groups
+                    .iter()
+                    .filter(|x| x.count > 0)
+                    .map(Group::mean)
+                    .collect_vec();
+                let centre = [0, 1, 2, 3]
+                    .map(|i| means.iter().map(|x| x[i]).sum::<f64>() / means.len() as f64);
+                let between = means
+                    .iter()
+                    .map(|x| (0..4).map(|i| (x[i] - centre[i]).powi(2)).sum::<f64>())
+                    .sum::<f64>();
+                if between > 0. {
+                    within() / between
+                } else {
+                    f64::INFINITY
+                }
+            }
+        }
+    }
+
+    /// Gives the colour with the given index in [FACES] to a facelet
+    fn set(&mut self, scorer: &Scorer, i: usize, colour: usize) {
+        let features = &scorer.features[i];
+        self.groups[self.notation[i]].remove(features);
+        self.groups[colour].add(features);
+        self.cost += scorer.costs[i][colour] - scorer.costs[i][self.notation[i]];
+        self.notation[i] = colour;
+    }
+
+    /// Swaps the colours of two facelets
+    pub fn swap(&mut self, scorer: &Scorer, i: usize, j: usize) {
+        let (a, b) = (self.notation[i], self.notation[j]);
+        if a != b {
+            self.set(scorer, i, b);
+            self.set(scorer, j, a);
+        }
+    }
+
+    pub fn apply(&mut self, scorer: &Scorer, mv: &Move) {
+        match *mv {
+            Move::Swap(i, j) => self.swap(scorer, i, j),
+            Move::Relabel(i, colour) => {
+                let colour = FACES.iter().position(|x| *x == colour).unwrap();
+                self.set(scorer, i, colour);
+            }
+            Move::Cycle(i, j, k) => {
+                self.swap(scorer, i, j);
+                self.swap(scorer, i, k);
+            }
+        }
+    }
+
+    /// Returns the score the notation would have once the move is applied, without applying it
+    pub fn score_after(&self, scorer: &Scorer, mv: &Move) -> f64 {
+        let mut moved = *self;
+        moved.apply(scorer, mv);
+        moved.score(scorer)
+    }
+
+    pub fn notation(&self) -> String {
+        self.notation.iter().map(|x| FACES[*x]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use crate::calibration::ColourModel;
+    use crate::classification::colour_centroids;
+    use crate::cube::Cube;
+    use crate::fixer::Move;
+    use crate::objective::{IncrementalScore, Objective, Scorer};
+
+    #[test]
+    fn incremental_score_matches_recomputation() {
+        let mut cube = Cube::init();
+        cube.import("scan_test_files/official_cube_solved/2024-06-05_23-36-17".to_string())
+            .expect("Could not load scan file");
+        let points = cube.feature_points();
+        let truth = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        let model = ColourModel::estimate(&[(points.clone(), truth.to_string())]);
+        for objective in Objective::value_variants() {
+            let scorer = Scorer::init(&points, *objective, Some(&model));
+            let mut chars = truth.chars().collect::<Vec<_>>();
+            let mut score = IncrementalScore::init(&scorer, &chars);
+            assert!(score.score(&scorer) >= 0.);
+            for mv in [
+                Move::Swap(0, 9),
+                Move::Relabel(7, 'B'),
+                Move::Cycle(1, 19, 50),
+                Move::Swap(0, 9),
+            ] {
+                let expected = score.score_after(&scorer, &mv);
+                score.apply(&scorer, &mv);
+                match mv {
+                    Move::Swap(i, j) => chars.swap(i, j),
+                    Move::Relabel(i, colour) => chars[i] = colour,
+                    Move::Cycle(i, j, k) => {
+                        chars.swap(i, j);
+                        chars.swap(i, k);
+                    }
+                }
+                let recomputed = IncrementalScore::init(&scorer, &chars);
+                assert_eq!(score.notation(), recomputed.notation());
+                assert!((score.score(&scorer) - expected).abs() < 1e-9);
+                assert!((score.score(&scorer) - recomputed.score(&scorer)).abs() < 1e-9);
+                if *objective == Objective::GroupMean {
+                    let chars = score.notation().chars().collect::<Vec<_>>();
+                    let means = colour_centroids(&points, &chars);
+                    let full = points
+                        .iter()
+                        .map(|x| x.distance_to(&means[&chars[x.index]]).powi(2))
+                        .sum::<f64>();
+                    assert!((score.score(&scorer) - full).abs() < 1e-6);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs::File;
+use std::io::Write;
+
+use crate::classification::confidences;
+use crate::constants::FACES;
+use crate::cube::Cube;
+use crate::state_format::StateFormat;
+
+/// Side of a sticker (in px)
+const STICKER: usize = 48;
+/// Space between two stickers of a face (in px)
+const GAP: usize = 4;
+/// Additional space between two faces (in px)
+const FACE_GAP: usize = 8;
+/// Space around the net (in px)
+const MARGIN: usize = 8;
+const BACKGROUND: [u8; 3] = [240, 240, 240];
+/// Column and row of every face in the unfolded net, in the order of [FACES]
+const FACE_ORIGINS: [(usize, usize); 6] = [(1, 0), (2, 1), (1, 1), (1, 2), (0, 1), (3, 1)];
+
+/// Facelet as drawn in the net
+#[derive(Clone, Copy, Debug)]
+pub struct Sticker {
+    pub fill: [u8; 3],
+    pub letter: char,
+    /// Confidence of the classification of the facelet, if it was scanned
+    pub confidence: Option<f64>,
+}
+
+impl Sticker {
+    /// Colour of the text written on the sticker, so that it stands out from the fill
+    fn ink(&self) -> [u8; 3] {
+        let [r, g, b] = self.fill.map(f64::from);
+        if 0.299 * r + 0.587 * g + 0.114 * b > 128. {
+            [0, 0, 0]
+        } else {
+            [255, 255, 255]
+        }
+    }
+}
+
+/// Unfolded net of a cube, with the same layout as [Cube::print_graphical]
+pub struct Net {
+    /// Stickers in the order of the standard notation
+    pub stickers: Vec<Sticker>,
+}
+
+impl Net {
+    /// Net of a scan: every sticker is filled with its reading, scaled so that the brightest channel is 255,
+    /// and shows the colour it was classified as, written in the format, along with the confidence of the classification
+    pub fn from_scan(cube: &Cube, notation: &str, format: &StateFormat) -> Self {
+        let max = cube
+            .facelet_rgb_values
+            .iter()
+            .flat_map(|x| x.to_array())
+            .fold(0., f64::max);
+        let scale = if max > 0. { 255. / max } else { 1. };
+        let confidences = confidences(&cube.feature_points(), notation);
+        let stickers = cube
+            .facelet_rgb_values
+            .iter()
+            .zip(notation.chars())
+            .zip(confidences)
+            .map(|((point, letter), confidence)| Sticker {
+                fill: point
+                    .to_array()
+                    .map(|x| (x * scale).round().clamp(0., 255.) as u8),
+                letter: format.letter(letter),
+                confidence: Some(confidence),
+            })
+            .collect();
+        Net { stickers }
+    }
+
+    /// Net of a notation, every sticker being filled with the colour of its face in the format
+    pub fn from_notation(notation: &str, format: &StateFormat) -> Self {
+        let colours = format.colours();
+        let stickers = notation
+            .chars()
+            .map(|letter| Sticker {
+                fill: FACES
+                    .iter()
+                    .position(|x| *x == letter)
+                    .map_or(BACKGROUND, |x| colours[x]),
+                letter: format.letter(letter),
+                confidence: None,
+            })
+            .collect();
+        Net { stickers }
+    }
+
+    fn width() -> usize {
+        2 * MARGIN + 4 * (3 * (STICKER + GAP) + FACE_GAP)
+    }
+
+    fn height() -> usize {
+        2 * MARGIN + 3 * (3 * (STICKER + GAP) + FACE_GAP)
+    }
+
+    /// Position of the top left corner of the sticker of a facelet (in px)
+    fn position(index: usize) -> (usize, usize) {
+        let (column, row) = FACE_ORIGINS[index / 9];
+        let face = 3 * (STICKER + GAP) + FACE_GAP;
+        (
+            MARGIN + column * face + (index % 3) * (STICKER + GAP),
+            MARGIN + row * face + (index % 9 / 3) * (STICKER + GAP),
+        )
+    }
+
+    /// Renders the net as an SVG document
+    pub fn to_svg(&self) -> String {
+        let (width, height) = (Self::width(), Self::height());
+        let rgb = |[r, g, b]: [u8; 3]| format!("rgb({r},{g},{b})");
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">\n"
+        );
+        svg.push_str(&format!(
+            "<rect width=\"{width}\" height=\"{height}\" fill=\"{}\"/>\n",
+            rgb(BACKGROUND)
+        ));
+        for (index, sticker) in self.stickers.iter().enumerate() {
+            let (x, y) = Self::position(index);
+            let centre = x + STICKER / 2;
+            svg.push_str(&format!(
+                "<g><title>facelet {index}</title><rect x=\"{x}\" y=\"{y}\" width=\"{STICKER}\" height=\"{STICKER}\" rx=\"4\" fill=\"{}\"/>",
+                rgb(sticker.fill)
+            ));
+            svg.push_str(&format!(
+                "<text x=\"{centre}\" y=\"{}\" font-family=\"monospace\" font-size=\"20\" text-anchor=\"middle\" fill=\"{}\">{}</text>",
+                y + 24,
+                rgb(sticker.ink()),
+                sticker.letter
+            ));
+            if let Some(confidence) = sticker.confidence {
+                svg.push_str(&format!(
+                    "<text x=\"{centre}\" y=\"{}\" font-family=\"monospace\" font-size=\"11\" text-anchor=\"middle\" fill=\"{}\">{confidence:.2}</text>",
+                    y + 42,
+                    rgb(sticker.ink())
+                ));
+            }
+            svg.push_str("</g>\n");
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Saves the net to a file as SVG
+    pub fn save(&self, file_path: &str) -> std::io::Result<()> {
+        File::create(file_path)?.write_all(self.to_svg().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cube::Cube;
+    use crate::render::Net;
+    use crate::state_format::{ColourScheme, StateFormat};
+
+    #[test]
+    fn test_render_net() {
+        let mut cube = Cube::init();
+        cube.import("scan_test_files/solvable.txt".to_string())
+            .expect("Could not load scan file");
+        let net = Net::from_scan(&cube, &cube.to_notation(), &StateFormat::default());
+        let svg = net.to_svg();
+        assert_eq!(svg.matches("<rect").count(), 55);
+        assert_eq!(svg.matches("font-size=\"11\"").count(), 54);
+        // stickers are filled with their reading, scaled so that the brightest channel is 255
+        let max = cube
+            .facelet_rgb_values
+            .iter()
+            .flat_map(|x| x.to_array())
+            .fold(0., f64::max);
+        for (sticker, point) in net.stickers.iter().zip(&cube.facelet_rgb_values) {
+            let expected = point.to_array().map(|x| (x * 255. / max).round() as u8);
+            assert_eq!(sticker.fill, expected);
+        }
+        assert!(net.stickers.iter().any(|x| x.fill.contains(&255)));
+        let [r, g, b] = net.stickers[0].fill;
+        let rect = svg.split("<title>facelet 0</title>").nth(1).unwrap();
+        let rect = rect.split("/>").next().unwrap();
+        assert!(rect.ends_with(&format!("fill=\"rgb({r},{g},{b})\"")));
+
+        let solved = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        let format = StateFormat {
+            scheme: Some(ColourScheme::parse("ygorwb").unwrap()),
+            ..Default::default()
+        };
+        let net = Net::from_notation(solved, &format);
+        let colours = format.colours();
+        // U is yellow and R is green in the scheme
+        assert_eq!(net.stickers[0].letter, 'Y');
+        assert_eq!(net.stickers[0].fill, colours[0]);
+        assert_eq!(net.stickers[9].letter, 'G');
+        assert_eq!(net.stickers[9].fill, colours[1]);
+        assert_ne!(colours, StateFormat::default().colours());
+    }
+}
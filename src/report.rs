@@ -0,0 +1,181 @@
+use std::fs::File;
+use std::io::Write;
+
+use itertools::Itertools;
+use paris::{info, log, warn};
+use serde::Serialize;
+
+use crate::classification::{colour_centroids, FeaturePoint};
+use crate::diagnosis::{diagnose, Violation};
+use crate::fixer::{calculate_score, SearchStats};
+
+/// Facelet whose colour was changed by the fixer
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FaceletChange {
+    /// Index of the facelet in the standard notation
+    pub index: usize,
+    /// Colour of the facelet in the original notation
+    pub from: char,
+    /// Colour of the facelet in the fixed notation
+    pub to: char,
+    /// Increase of the distance between the facelet and the mean of its colour.
+    /// Means are taken from the original notation, so that the changes can be compared with each other
+    pub cost_delta: f64,
+}
+
+/// Explains how the fixer turned a notation into a valid one
+#[derive(Serialize, Clone, Debug)]
+pub struct FixReport {
+    /// Notation given to the fixer
+    pub original: String,
+    /// Score of the original notation
+    pub original_score: f64,
+    /// Valid notation found by the fixer
+    pub fixed: String,
+    /// Score of the fixed notation
+    pub score: f64,
+    /// Reasons why the original notation was not valid
+    pub violations: Vec<Violation>,
+    /// Facelets whose colour changed, costliest first
+    pub changes: Vec<FaceletChange>,
+    /// Statistics of the fixer run
+    pub stats: SearchStats,
+}
+
+impl FixReport {
+    /// Builds the report of a fix from the original notation, the fixed one and its score
+    pub fn init(
+        rgb_values: &[FeaturePoint],
+        original: String,
+        (score, fixed): (f64, String),
+        stats: SearchStats,
+    ) -> Self {
+        let before = original.chars().collect_vec();
+        let after = fixed.chars().collect_vec();
+        let means = colour_centroids(rgb_values, &before);
+        let fallback = colour_centroids(rgb_values, &after);
+        let distance = |i: usize, c: char| {
+            means
+                .get(&c)
+                .or(fallback.get(&c))
+                .map_or(0., |m| rgb_values[i].distance_to(m))
+        };
+        let changes = (0..54)
+            .filter(|i| before[*i] != after[*i])
+            .map(|index| FaceletChange {
+                index,
+                from: before[index],
+                to: after[index],
+                cost_delta: distance(index, after[index]) - distance(index, before[index]),
+            })
+            .sorted_by(|a, b| b.cost_delta.total_cmp(&a.cost_delta))
+            .collect();
+        FixReport {
+            original_score: calculate_score(rgb_values, &original),
+            violations: diagnose(&original),
+            original,
+            fixed,
+            score,
+            changes,
+            stats,
+        }
+    }
+
+    /// Prints the report in the terminal
+    pub fn print(&self) {
+        if self.violations.is_empty() {
+            info!("Original notation was already valid");
+        } else {
+            info!("Original notation was invalid:");
+            for violation in &self.violations {
+                log!("  - {violation}");
+            }
+        }
+        info!("{} facelets changed:", self.changes.len());
+        for change in &self.changes {
+            log!(
+                "  - facelet {}: {} -> {} (cost {:+.4})",
+                change.index,
+                change.from,
+                change.to,
+                change.cost_delta
+            );
+        }
+        info!(
+            "Score went from {:.4} to {:.4}",
+            self.original_score, self.score
+        );
+        let depth = self
+            .stats
+            .depth
+            .map_or(String::new(), |x| format!(" up to depth {x}"));
+        info!(
+            "Evaluated {} candidates{depth} in {:.2?}",
+            self.stats.candidates, self.stats.elapsed
+        );
+        if self.stats.stopped {
+            warn!("The search was stopped early, keeping the best valid state found so far");
+        }
+    }
+
+    /// Saves the report to a JSON file
+    pub fn save(&self, file_path: &str) -> std::io::Result<()> {
+        let mut file = File::create(file_path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::classification::FeaturePoint;
+    use crate::diagnosis::Violation;
+    use crate::fixer::SearchStats;
+    use crate::report::FixReport;
+
+    const SOLVED: &str = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+
+    #[test]
+    fn test_fix_report() {
+        let points = (0..54)
+            .map(|index| FeaturePoint {
+                r: (index / 9) as f64,
+                g: 0.,
+                b: 0.,
+                l: 0.,
+                index,
+            })
+            .collect::<Vec<_>>();
+        // the UF edge is flipped
+        let mut chars = SOLVED.chars().collect::<Vec<_>>();
+        chars.swap(7, 19);
+        let flipped: String = chars.iter().collect();
+        let report = FixReport::init(
+            &points,
+            flipped.clone(),
+            (0., SOLVED.to_string()),
+            SearchStats::default(),
+        );
+        assert_eq!(report.original, flipped);
+        assert_eq!(report.fixed, SOLVED);
+        assert!(report.original_score > report.score);
+        assert_eq!(report.violations, vec![Violation::EdgeFlip { sum: 1 }]);
+        let changes = report
+            .changes
+            .iter()
+            .map(|x| (x.index, x.from, x.to))
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec![(7, 'F', 'U'), (19, 'U', 'F')]);
+        // moving a facelet back to the colour of its reading brings it closer to its mean
+        assert!(report.changes.iter().all(|x| x.cost_delta < 0.));
+
+        let report = FixReport::init(
+            &points,
+            SOLVED.to_string(),
+            (0., SOLVED.to_string()),
+            SearchStats::default(),
+        );
+        assert!(report.violations.is_empty());
+        assert!(report.changes.is_empty());
+        assert_eq!(report.original_score, 0.);
+    }
+}
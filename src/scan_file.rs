@@ -0,0 +1,392 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregation::Aggregation;
+use crate::cube::Cube;
+
+/// Version of the scan file format written by [Cube::save](crate::cube::Cube::save).
+/// Files without a version are the legacy format of one `r, g, b` line per facelet.
+pub const SCAN_FORMAT_VERSION: u32 = 1;
+
+/// Largest raw reading of the color sensor in RGB mode
+pub const MAX_READING: f64 = 1020.;
+
+/// Unit of the readings of a scan. Readings of different units cannot be compared
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingUnit {
+    /// Raw readings of the color sensor, from 0 to [MAX_READING]
+    #[default]
+    Raw,
+    /// Readings scaled to unit length, as saved in legacy scan files. Their luminance is lost
+    Normalised,
+}
+
+impl Display for ReadingUnit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadingUnit::Raw => write!(f, "raw"),
+            ReadingUnit::Normalised => write!(f, "normalised"),
+        }
+    }
+}
+
+/// Error found while parsing a legacy scan file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Line of the error, starting at 1
+    pub line: usize,
+    /// Column of the error, in characters, starting at 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Checks that a reading is a number the color sensor can return
+fn check_reading(value: f64) -> Result<(), String> {
+    if !value.is_finite() {
+        Err(format!("{value} is not a finite number"))
+    } else if value < 0. {
+        Err(format!("reading {value} is negative"))
+    } else if value > MAX_READING {
+        Err(format!(
+            "reading {value} is above the sensor maximum of {MAX_READING}"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Parses the three values of a reading, separated by commas and/or whitespace.
+/// `column` is the column of the first character of the reading.
+fn parse_reading(reading: &str, line: usize, column: usize) -> Result<[f64; 3], ParseError> {
+    let error = |offset: usize, message: String| ParseError {
+        line,
+        column: column + offset,
+        message,
+    };
+    let chars = reading.chars().collect::<Vec<_>>();
+    let mut values = vec![];
+    let mut comma = None;
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+        } else if chars[i] == ',' {
+            if values.is_empty() || comma.is_some() {
+                return Err(error(i, "unexpected comma".to_string()));
+            }
+            comma = Some(i);
+            i += 1;
+        } else {
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() && chars[i] != ',' {
+                i += 1;
+            }
+            let token = chars[start..i].iter().collect::<String>();
+            if values.len() == 3 {
+                return Err(error(
+                    start,
+                    format!("unexpected value {token}, a reading has 3 values"),
+                ));
+            }
+            let value = token
+                .parse::<f64>()
+                .map_err(|_| error(start, format!("{token} is not a number")))?;
+            check_reading(value).map_err(|message| error(start, message))?;
+            values.push(value);
+            comma = None;
+        }
+    }
+    if let Some(comma) = comma {
+        return Err(error(comma, "unexpected comma".to_string()));
+    }
+    match values[..] {
+        [r, g, b] => Ok([r, g, b]),
+        _ => Err(error(
+            chars.len(),
+            format!("expected 3 values, found {}", values.len()),
+        )),
+    }
+}
+
+/// Settings and context a scan was made with. Every field is optional, as older scans do not record them
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ScanMetadata {
+    /// Version of the software that made the scan
+    pub software_version: Option<String>,
+    /// Date and time of the scan, in RFC 3339 format
+    pub date: Option<String>,
+    /// Number of color sensor scans per facelet
+    pub iteration: Option<usize>,
+    /// Movement between each color sensor scan
+    pub movement: Option<i32>,
+    /// Sleep duration between each color sensor scan (in ms)
+    pub sleep: Option<u32>,
+    /// Method used to reduce the scans of a facelet into a single reading
+    pub aggregation: Option<Aggregation>,
+    /// Name of the robot the scan was made with
+    pub robot: Option<String>,
+    /// Model of the scanned cube
+    pub cube_model: Option<String>,
+}
+
+impl ScanMetadata {
+    /// Metadata of a scan made now by this version of the software
+    pub fn now() -> Self {
+        ScanMetadata {
+            software_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            date: Some(chrono::Utc::now().to_rfc3339()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Readings of a facelet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FaceletScan {
+    /// Aggregated RGB value
+    pub rgb: [f64; 3],
+    /// Raw color sensor samples, empty if unknown
+    #[serde(default)]
+    pub samples: Vec<[f64; 3]>,
+}
+
+/// Content of a versioned scan file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScanFile {
+    pub version: u32,
+    #[serde(default)]
+    pub metadata: ScanMetadata,
+    /// True notation of the scanned cube, if known
+    #[serde(default)]
+    pub truth: Option<String>,
+    /// Unit of the readings. Raw if missing, as every versioned file written before it was saved held raw readings
+    #[serde(default)]
+    pub unit: ReadingUnit,
+    /// Readings of every facelet, in the order of the standard notation
+    pub facelets: Vec<FaceletScan>,
+}
+
+impl ScanFile {
+    /// Parses a versioned scan file, checking its version, its number of facelets and its true notation
+    pub fn parse(content: &str) -> std::io::Result<Self> {
+        let invalid =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        let mut scan: ScanFile = serde_json::from_str(content)?;
+        if scan.version > SCAN_FORMAT_VERSION {
+            return Err(invalid(format!(
+                "Scan file version {} is newer than the supported version {SCAN_FORMAT_VERSION}",
+                scan.version
+            )));
+        }
+        if scan.facelets.len() != 54 {
+            return Err(invalid(format!(
+                "Scan file holds {} facelets instead of 54",
+                scan.facelets.len()
+            )));
+        }
+        for (index, facelet) in scan.facelets.iter().enumerate() {
+            for value in facelet.rgb.iter().chain(facelet.samples.iter().flatten()) {
+                check_reading(*value).map_err(|e| invalid(format!("Facelet {index}: {e}")))?;
+            }
+        }
+        if let Some(truth) = &scan.truth {
+            let truth = Cube::parse_state(truth)
+                .map_err(|e| invalid(format!("Invalid true notation {truth}: {e}")))?;
+            scan.truth = Some(truth);
+        }
+        Ok(scan)
+    }
+
+    /// Parses a legacy scan file, made of exactly 54 facelet lines in the order of the standard notation.
+    /// Legacy files hold [ReadingUnit::Normalised] readings, but their raw samples are [ReadingUnit::Raw] ones.
+    /// A line holds the reading of a facelet, followed by its raw samples, separated by semicolons.
+    /// The values of a reading are separated by commas and/or whitespace, and `#` starts a comment.
+    pub fn parse_legacy(content: &str) -> Result<Vec<FaceletScan>, ParseError> {
+        let mut facelets = vec![];
+        let mut lines = 0;
+        for (i, line) in content.split('\n').enumerate() {
+            lines = i + 1;
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let line = line.split('#').next().unwrap_or_default();
+            if line.trim().is_empty() {
+                continue;
+            }
+            if facelets.len() == 54 {
+                return Err(ParseError {
+                    line: i + 1,
+                    column: 1,
+                    message: "more than 54 facelets".to_string(),
+                });
+            }
+            let mut readings = vec![];
+            let mut column = 1;
+            for part in line.split(';') {
+                readings.push(parse_reading(part, i + 1, column)?);
+                column += part.chars().count() + 1;
+            }
+            facelets.push(FaceletScan {
+                rgb: readings[0],
+                samples: readings[1..].to_vec(),
+            });
+        }
+        if facelets.len() < 54 {
+            return Err(ParseError {
+                line: lines,
+                column: 1,
+                message: format!("only {} facelets, expected 54", facelets.len()),
+            });
+        }
+        Ok(facelets)
+    }
+
+    /// Returns true if the content looks like a versioned scan file rather than a legacy one
+    pub fn is_versioned(content: &str) -> bool {
+        content.trim_start().starts_with('{')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregation::Aggregation;
+    use crate::cube::Cube;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::scan_file::{ParseError, ReadingUnit, ScanFile, ScanMetadata, MAX_READING};
+
+    #[test]
+    fn versioned_scan_round_trip() {
+        let mut legacy = Cube::init();
+        legacy
+            .import("scan_test_files/solvable.txt".to_string())
+            .expect("Could not load scan file");
+        assert_eq!(legacy.unit, ReadingUnit::Normalised);
+        assert!(legacy.feature_points().iter().all(|x| x.l == 0.));
+        legacy.metadata = ScanMetadata {
+            iteration: Some(5),
+            aggregation: Some(Aggregation::TrimmedMean),
+            robot: Some("test".to_string()),
+            ..ScanMetadata::now()
+        };
+        legacy.truth = Some("UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB".to_string());
+        legacy.raw_samples[3] = vec![[1., 2., 3.], [4., 5., 6.]];
+        let path = std::env::temp_dir().join(format!(
+            "mindsolver_versioned_scan_{}.json",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap().to_string();
+        legacy.save(&path).expect("Could not save scan file");
+
+        let mut versioned = Cube::init();
+        versioned
+            .import(path.clone())
+            .expect("Could not load scan file");
+        std::fs::remove_file(&path).ok();
+        assert_eq!(versioned.metadata, legacy.metadata);
+        assert_eq!(versioned.truth, legacy.truth);
+        assert_eq!(versioned.unit, ReadingUnit::Normalised);
+        assert_eq!(versioned.raw_samples, legacy.raw_samples);
+        for (a, b) in versioned
+            .facelet_rgb_values
+            .iter()
+            .zip(&legacy.facelet_rgb_values)
+        {
+            assert_eq!(a.to_array(), b.to_array());
+            assert_eq!(a.index, b.index);
+        }
+
+        let facelets = vec![r#"{"rgb": [1, 2, 3]}"#; 54].join(",");
+        let raw = ScanFile::parse(&format!(r#"{{"version": 1, "facelets": [{facelets}]}}"#));
+        assert_eq!(raw.unwrap().unit, ReadingUnit::Raw);
+        assert!(ScanFile::parse(r#"{"version": 1, "facelets": []}"#).is_err());
+        let solved = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        let with_truth = |truth: &str| {
+            ScanFile::parse(&format!(
+                r#"{{"version": 1, "truth": "{truth}", "facelets": [{facelets}]}}"#
+            ))
+        };
+        assert_eq!(
+            with_truth(&solved.to_lowercase()).unwrap().truth.as_deref(),
+            Some(solved)
+        );
+        assert!(with_truth(&solved.replace('B', "X")).is_err());
+        assert!(with_truth(&solved.replacen('U', "R", 1)).is_err());
+        assert!(ScanFile::parse(r#"{"version": 99, "facelets": []}"#).is_err());
+    }
+
+    fn error_at(content: &str) -> (usize, usize) {
+        let ParseError { line, column, .. } = ScanFile::parse_legacy(content).unwrap_err();
+        (line, column)
+    }
+
+    #[test]
+    fn test_parse_legacy() {
+        let line = "1, 2, 3; 4,5,6 ;7 8\t9\n";
+        let content = format!("# scan of a solved cube\n{}", line.repeat(54));
+        let facelets = ScanFile::parse_legacy(&content).unwrap();
+        assert_eq!(facelets.len(), 54);
+        assert_eq!(facelets[53].rgb, [1., 2., 3.]);
+        assert_eq!(facelets[53].samples, vec![[4., 5., 6.], [7., 8., 9.]]);
+        let content = "1, 2, 3 # comment\r\n\n".repeat(54);
+        assert!(ScanFile::parse_legacy(&content).is_ok());
+
+        let valid = "1, 2, 3\n".repeat(53);
+        assert_eq!(error_at(&valid), (54, 1));
+        assert_eq!(error_at(&format!("{valid}1, 2, 3\n4, 5, 6\n")), (55, 1));
+        assert_eq!(error_at(&format!("1, x, 3\n{valid}")), (1, 4));
+        assert_eq!(error_at(&format!("1, 2,, 3\n{valid}")), (1, 6));
+        assert_eq!(error_at(&format!("1, 2, 3,\n{valid}")), (1, 8));
+        assert_eq!(error_at(&format!("1, 2\n{valid}")), (1, 5));
+        assert_eq!(error_at(&format!("1, 2, 3, 4\n{valid}")), (1, 10));
+        assert_eq!(error_at(&format!("{valid}1, 2, 3; 4, NaN, 6\n")), (54, 13));
+        assert_eq!(error_at(&format!("{valid}1, 2, 3;\n")), (54, 9));
+        assert_eq!(error_at(&format!("-1, 2, 3\n{valid}")), (1, 1));
+        assert_eq!(error_at(&format!("1, 2, 1e9\n{valid}")), (1, 7));
+    }
+
+    /// Mutates valid scan files at random: the parser must never panic, and only accept valid readings
+    #[test]
+    fn test_parse_legacy_fuzz() {
+        let original = std::fs::read_to_string("scan_test_files/solvable.txt").unwrap();
+        let alphabet = b"0123456789.,;# \t\n\r-+eEnaNifINF";
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..2000 {
+            let mut content = original.clone().into_bytes();
+            for _ in 0..rng.gen_range(1..8) {
+                let position = rng.gen_range(0..content.len());
+                match rng.gen_range(0..3) {
+                    0 => content.insert(position, alphabet[rng.gen_range(0..alphabet.len())]),
+                    1 => {
+                        content.remove(position);
+                    }
+                    _ => content[position] = alphabet[rng.gen_range(0..alphabet.len())],
+                }
+            }
+            let content = String::from_utf8(content).unwrap();
+            if let Ok(facelets) = ScanFile::parse_legacy(&content) {
+                assert_eq!(facelets.len(), 54);
+                for value in facelets
+                    .iter()
+                    .flat_map(|x| x.rgb.iter().chain(x.samples.iter().flatten()))
+                {
+                    assert!(value.is_finite() && (0. ..=MAX_READING).contains(value));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use kewb::Solution;
+
+use crate::hardware::Primitive;
+
+/// Format the solution is printed in
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum SolutionFormat {
+    /// Moves as given by the solver
+    #[default]
+    Kewb,
+    /// WCA/SiGN notation, consecutive turns of the same face being merged
+    Sign,
+    /// Quarter-turn metric expansion, where every half turn is written as two quarter turns
+    Qtm,
+    /// Number of moves in the half-turn and quarter-turn metrics
+    Counts,
+    /// Robot primitives applying the solution
+    Primitives,
+}
+
+/// Turns of the solution as faces and numbers of clockwise quarter turns (1 to 3),
+/// consecutive turns of the same face being merged and cancelled turns dropped
+fn merged_turns(solution: &Solution) -> Vec<(char, u8)> {
+    let mut turns: Vec<(char, u8)> = vec![];
+    for part in solution.get_all_moves() {
+        let part = part.to_string();
+        let face = part.chars().next().unwrap();
+        let quarters = match part.chars().nth(1) {
+            None => 1,
+            Some('2') => 2,
+            _ => 3,
+        };
+        match turns.last_mut() {
+            Some((last, total)) if *last == face => {
+                *total = (*total + quarters) % 4;
+                if *total == 0 {
+                    turns.pop();
+                }
+            }
+            _ => turns.push((face, quarters)),
+        }
+    }
+    turns
+}
+
+/// Writes a turn in WCA/SiGN notation
+fn sign(face: char, quarters: u8) -> String {
+    match quarters {
+        1 => face.to_string(),
+        2 => format!("{face}2"),
+        _ => format!("{face}'"),
+    }
+}
+
+/// Number of moves of the solution in the half-turn metric and in the quarter-turn metric
+fn move_counts(solution: &Solution) -> (usize, usize) {
+    let turns = merged_turns(solution);
+    let quarter_turns = turns.iter().map(|(_, x)| if *x == 2 { 2 } else { 1 }).sum();
+    (turns.len(), quarter_turns)
+}
+
+/// Writes the solution in the given format. The primitives are only used by [SolutionFormat::Primitives]
+pub fn format_solution(
+    solution: &Solution,
+    format: SolutionFormat,
+    primitives: &[Primitive],
+) -> String {
+    match format {
+        SolutionFormat::Kewb => solution.to_string(),
+        SolutionFormat::Sign => merged_turns(solution)
+            .into_iter()
+            .map(|(face, quarters)| sign(face, quarters))
+            .collect::<Vec<_>>()
+            .join(" "),
+        SolutionFormat::Qtm => merged_turns(solution)
+            .into_iter()
+            .map(|(face, quarters)| match quarters {
+                2 => format!("{face} {face}"),
+                _ => sign(face, quarters),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        SolutionFormat::Counts => {
+            let (htm, qtm) = move_counts(solution);
+            format!("{htm} HTM, {qtm} QTM")
+        }
+        SolutionFormat::Primitives => primitives
+            .iter()
+            .map(Primitive::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kewb::Move::*;
+    use kewb::Solution;
+
+    use crate::cube::Cube;
+    use crate::hardware::{plan_solution, Hardware, Primitive};
+    use crate::solution::{format_solution, SolutionFormat};
+
+    #[test]
+    fn test_solution_formats() {
+        // the turns of the last R of the first phase and of the first R of the second phase are merged
+        let solution = Solution {
+            phase1: vec![F2, R],
+            phase2: vec![R, U3, U, D3],
+        };
+        let cube = Cube::init();
+        let primitives = plan_solution(&solution, Hardware::orientation(&cube), false);
+        let format = |format| format_solution(&solution, format, &primitives);
+        assert_eq!(format(SolutionFormat::Kewb), "F2 R R U' U D'");
+        assert_eq!(format(SolutionFormat::Sign), "F2 R2 D'");
+        assert_eq!(format(SolutionFormat::Qtm), "F F R R D'");
+        assert_eq!(format(SolutionFormat::Counts), "3 HTM, 5 QTM");
+        assert_eq!(
+            primitives[..4],
+            [
+                Primitive::BaseTurn { clockwise: true },
+                Primitive::Lock,
+                Primitive::Flip,
+                Primitive::FaceTurn { quarters: 2 },
+            ]
+        );
+        let face_turns = primitives
+            .iter()
+            .filter(|x| matches!(x, Primitive::FaceTurn { .. }))
+            .count();
+        assert_eq!(face_turns, 6);
+        assert!(format(SolutionFormat::Primitives).starts_with("base_turn_90, lock, flip"));
+    }
+}
@@ -0,0 +1,201 @@
+use itertools::Itertools;
+
+use crate::constants::{FACES, FACE_COLOURS};
+use crate::cube::StateError;
+
+/// Colours a scheme can give to the faces, with the colour they are displayed with
+const PALETTE: [(char, [u8; 3]); 6] = [
+    ('W', [255, 255, 255]),
+    ('Y', [255, 255, 0]),
+    ('R', [255, 0, 0]),
+    ('O', [255, 165, 0]),
+    ('G', [0, 255, 0]),
+    ('B', [0, 0, 255]),
+];
+
+/// Order of the facelets in a cube state, as used by solvers and apps.
+/// Every face is read as seen from the outside, with the same orientation as in the standard notation
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum FaceletOrder {
+    /// Faces in the order U, R, F, D, L, B, each read row by row. This is the standard notation, used by kewb
+    #[default]
+    Urfdlb,
+    /// Faces in the order U, L, F, R, B, D, each read row by row
+    Ulfrbd,
+    /// Faces in the order U, R, F, D, L, B, each read column by column
+    UrfdlbColumns,
+    /// Faces in the order U, L, F, R, B, D, each read column by column
+    UlfrbdColumns,
+}
+
+impl FaceletOrder {
+    /// Indices in the standard notation of the facelets, in this order
+    fn indices(self) -> Vec<usize> {
+        let (faces, column_major) = match self {
+            FaceletOrder::Urfdlb => ("URFDLB", false),
+            FaceletOrder::Ulfrbd => ("ULFRBD", false),
+            FaceletOrder::UrfdlbColumns => ("URFDLB", true),
+            FaceletOrder::UlfrbdColumns => ("ULFRBD", true),
+        };
+        faces
+            .chars()
+            .flat_map(|face| {
+                let face = FACES.iter().position(|x| *x == face).unwrap();
+                (0..9).map(move |i| {
+                    let i = if column_major { i % 3 * 3 + i / 3 } else { i };
+                    9 * face + i
+                })
+            })
+            .collect()
+    }
+}
+
+/// Colour of every face, in the order of [FACES], such as `WBRYGO` for a white U face, a blue R face and so on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColourScheme {
+    pub letters: [char; 6],
+}
+
+impl ColourScheme {
+    /// Parses the colours of the faces, given as 6 distinct letters among W, Y, R, O, G and B
+    pub fn parse(scheme: &str) -> Result<Self, String> {
+        let letters = scheme.trim().to_uppercase().chars().collect_vec();
+        let letters: [char; 6] = letters
+            .try_into()
+            .map_err(|_| "a colour scheme gives the colours of the 6 faces".to_string())?;
+        if let Some(letter) = letters
+            .iter()
+            .find(|x| !PALETTE.iter().any(|(colour, _)| colour == *x))
+        {
+            return Err(format!(
+                "{letter} is not one of the colours W, Y, R, O, G and B"
+            ));
+        }
+        if !letters.iter().all_unique() {
+            return Err("every face needs a different colour".to_string());
+        }
+        Ok(ColourScheme { letters })
+    }
+
+    /// Colour every face is displayed with, in the order of [FACES]
+    fn colours(&self) -> [[u8; 3]; 6] {
+        self.letters
+            .map(|x| PALETTE.iter().find(|(colour, _)| *colour == x).unwrap().1)
+    }
+}
+
+/// Order and letters of the cube states exchanged with the user, other solvers and apps
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StateFormat {
+    pub order: FaceletOrder,
+    /// Colours standing for the faces. Faces are written with their own letter if there is none
+    pub scheme: Option<ColourScheme>,
+}
+
+impl StateFormat {
+    /// Converts a state written in this format into the standard notation.
+    /// Only the letters and their number are checked, not whether the state is valid
+    pub fn import(&self, state: &str) -> Result<String, StateError> {
+        let chars = state.trim().to_uppercase().chars().collect_vec();
+        if chars.len() != 54 {
+            return Err(StateError::Length(chars.len()));
+        }
+        let faces = chars
+            .iter()
+            .map(|letter| match &self.scheme {
+                Some(scheme) => scheme
+                    .letters
+                    .iter()
+                    .position(|x| x == letter)
+                    .map(|x| FACES[x])
+                    .ok_or(StateError::UnknownColour(*letter)),
+                None => Ok(*letter),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut notation = vec![' '; 54];
+        for (i, index) in self.order.indices().into_iter().enumerate() {
+            notation[index] = faces[i];
+        }
+        Ok(notation.into_iter().collect())
+    }
+
+    /// Writes a notation in this format
+    pub fn export(&self, notation: &str) -> String {
+        let chars = notation.chars().collect_vec();
+        self.order
+            .indices()
+            .into_iter()
+            .map(|x| self.letter(chars[x]))
+            .collect()
+    }
+
+    /// Letter standing for the face in this format. Unknown faces are kept as they are
+    pub fn letter(&self, face: char) -> char {
+        match (&self.scheme, FACES.iter().position(|x| *x == face)) {
+            (Some(scheme), Some(face)) => scheme.letters[face],
+            _ => face,
+        }
+    }
+
+    /// Colour every face is displayed with, in the order of [FACES]
+    pub fn colours(&self) -> [[u8; 3]; 6] {
+        self.scheme.map_or(FACE_COLOURS, |x| x.colours())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use crate::cube::{Cube, StateError};
+    use crate::state_format::{ColourScheme, FaceletOrder, StateFormat};
+
+    #[test]
+    fn test_state_formats() {
+        let scrambled = Cube::scrambled("R U2 F' D B2 L").unwrap();
+        let western = ColourScheme::parse("wbrygo").unwrap();
+        for order in FaceletOrder::value_variants() {
+            for scheme in [None, Some(western)] {
+                let format = StateFormat {
+                    order: *order,
+                    scheme,
+                };
+                let exported = format.export(&scrambled);
+                assert_eq!(format.import(&exported).unwrap(), scrambled);
+            }
+        }
+
+        let solved = "UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB";
+        let format = StateFormat {
+            order: FaceletOrder::Ulfrbd,
+            scheme: Some(western),
+        };
+        assert_eq!(
+            format.export(solved),
+            "WWWWWWWWWGGGGGGGGGRRRRRRRRRBBBBBBBBBOOOOOOOOOYYYYYYYYY"
+        );
+        // the second facelet of a face read column by column is the fourth one read row by row
+        let format = StateFormat {
+            order: FaceletOrder::UrfdlbColumns,
+            scheme: None,
+        };
+        assert_eq!(format.export(&scrambled)[1..2], scrambled[3..4]);
+        assert_eq!(format.export(&scrambled)[4..5], scrambled[4..5]);
+
+        assert!(matches!(
+            StateFormat {
+                order: FaceletOrder::Urfdlb,
+                scheme: Some(western),
+            }
+            .import(solved),
+            Err(StateError::UnknownColour('U'))
+        ));
+        assert!(matches!(
+            StateFormat::default().import(&solved[1..]),
+            Err(StateError::Length(53))
+        ));
+        assert!(ColourScheme::parse("WBRYGG").is_err());
+        assert!(ColourScheme::parse("WBRYGX").is_err());
+        assert!(ColourScheme::parse("WBRYG").is_err());
+    }
+}